@@ -0,0 +1,158 @@
+//! [`Serialize`] and [`Deserialize`] for [`ArbitrageFinder`], so that the book can be
+//! checkpointed to disk and restored, or attached to a bug report.
+//!
+//! Levels are stored as flat `(price, exchange_id, quantity)` sequences rather than maps,
+//! so that formats like JSON don't need prices or exchange ids to be strings.
+//! The per-exchange index, and its running depth, is rebuilt on deserialization.
+
+use std::{
+    hash::{BuildHasher, Hash},
+    ops::Sub,
+};
+
+use num_traits::Zero;
+use serde::{
+    de::Error as _, ser::SerializeStruct as _, Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{insert, ArbitrageFinder, Fees, Half};
+
+impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT> Serialize
+    for ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    QuantityT: Serialize,
+    PriceT: Serialize,
+    ExchangeIdT: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Self { bids, asks, fees } = self;
+        let mut state = serializer.serialize_struct("ArbitrageFinder", 3)?;
+        state.serialize_field("bids", &Levels(bids))?;
+        state.serialize_field("asks", &Levels(asks))?;
+        state.serialize_field("fees", &fees.iter().collect::<Vec<_>>())?;
+        state.end()
+    }
+}
+
+impl<'de, QuantityT, PriceT, ExchangeIdT, BuildHasherT> Deserialize<'de>
+    for ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    QuantityT: Deserialize<'de> + Zero + Clone + Sub<Output = QuantityT>,
+    PriceT: Deserialize<'de> + Ord + Clone,
+    ExchangeIdT: Deserialize<'de> + Eq + Hash + Clone,
+    BuildHasherT: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Checkpoint { bids, asks, fees } =
+            Checkpoint::<QuantityT, PriceT, ExchangeIdT>::deserialize(deserializer)?;
+        let mut finder = Self::default();
+        for (half, levels) in [(&mut finder.bids, bids), (&mut finder.asks, asks)] {
+            for (price, exchange_id, quantity) in levels {
+                // zero means removed, and the index assumes one quantity per exchange and price
+                if quantity.is_zero() {
+                    return Err(D::Error::custom("level has a quantity of zero"));
+                }
+                if half
+                    .levels
+                    .get(&price)
+                    .is_some_and(|it| it.contains_key(&exchange_id))
+                {
+                    return Err(D::Error::custom(
+                        "duplicate level for an exchange and price",
+                    ));
+                }
+                insert(half, price, exchange_id, quantity)
+            }
+        }
+        finder.fees.extend(fees);
+        Ok(finder)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(bound(
+    deserialize = "QuantityT: Deserialize<'de>, PriceT: Deserialize<'de>, ExchangeIdT: Deserialize<'de>"
+))]
+struct Checkpoint<QuantityT, PriceT, ExchangeIdT> {
+    bids: Vec<(PriceT, ExchangeIdT, QuantityT)>,
+    asks: Vec<(PriceT, ExchangeIdT, QuantityT)>,
+    fees: Vec<(ExchangeIdT, Fees<PriceT>)>,
+}
+
+struct Levels<'a, QuantityT, PriceT, ExchangeIdT, BuildHasherT>(
+    &'a Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+);
+
+impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT> Serialize
+    for Levels<'_, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    QuantityT: Serialize,
+    PriceT: Serialize,
+    ExchangeIdT: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.levels.iter().flat_map(|(price, xcs)| {
+            xcs.iter()
+                .map(move |(exchange_id, quantity)| (price, exchange_id, quantity))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::assert_equal;
+    use serde_json::json;
+
+    use crate::Side;
+
+    use super::*;
+
+    type Finder = ArbitrageFinder<u32, u32, String>;
+
+    #[test]
+    fn round_trip() {
+        let kraken = || String::from("kraken");
+        let mut finder = Finder::default();
+        drop(finder.buy(kraken(), 10, 1).unwrap());
+        drop(finder.buy("binance".into(), 10, 2).unwrap());
+        drop(finder.sell(kraken(), 20, 3).unwrap());
+        finder.set_fees(kraken(), Fees { maker: 0, taker: 1 });
+
+        let json = serde_json::to_value(&finder).unwrap();
+        let mut bids = json["bids"].as_array().unwrap().clone();
+        bids.sort_by_key(|it| it.to_string());
+        assert_eq!(bids, [json!([10, "binance", 2]), json!([10, "kraken", 1])]);
+        assert_eq!(json["asks"], json!([[20, "kraken", 3]]));
+        assert_eq!(json["fees"], json!([["kraken", {"maker": 0, "taker": 1}]]));
+
+        let mut restored = serde_json::from_value::<Finder>(json).unwrap();
+        assert_eq!(restored.fees(&kraken()), finder.fees(&kraken()));
+        assert_equal(restored.exchange(&kraken()).levels(Side::Ask), [(&20, &3)]);
+        // the index is rebuilt
+        restored.remove_exchange(&kraken());
+        assert_eq!(restored.sell("coinbase".into(), 1, 1).unwrap().count(), 1);
+        assert_eq!(restored.best_ask().map(|(price, _)| *price), Some(1));
+    }
+
+    #[test]
+    fn zero_quantity() {
+        let err = serde_json::from_value::<Finder>(json!({
+            "bids": [[10, "kraken", 0]],
+            "asks": [],
+            "fees": []
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("zero"), "{err}");
+    }
+
+    #[test]
+    fn duplicate_level() {
+        let err = serde_json::from_value::<Finder>(json!({
+            "bids": [],
+            "asks": [[20, "kraken", 1], [20, "binance", 1], [20, "kraken", 2]],
+            "fees": []
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("duplicate"), "{err}");
+    }
+}
@@ -0,0 +1,329 @@
+//! Triangular (and longer) arbitrage across currency pairs.
+//!
+//! Each pair's [`ArbitrageFinder`] contributes two edges per exchange to a graph
+//! of currencies:
+//! - buying the base currency from the best ask, and
+//! - selling the base currency to the best bid.
+//!
+//! Each edge is weighted by the negative log of its rate after taker [`Fees`](crate::Fees),
+//! so a cycle of negative weight ends up with more than it started with.
+//! These are found with [Bellman-Ford](https://cp-algorithms.com/graph/finding-negative-cycle-in-graph.html).
+//!
+//! Cycles may span exchanges, or stay within one.
+//! Arithmetic is done in [`f64`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasher, Hash},
+};
+
+use num_traits::ToPrimitive;
+
+use crate::{ArbitrageFinder, Side};
+
+/// A sequence of trades that ends with more of the starting currency than it began with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cycle<CurrencyT, PriceT, ExchangeIdT> {
+    /// The `to` of each leg is the `from` of the next, wrapping around.
+    pub legs: Vec<Leg<CurrencyT, PriceT, ExchangeIdT>>,
+    /// The most that can be put through every leg at its best price,
+    /// in units of the first leg's `from` currency.
+    pub size: f64,
+    /// The fractional return after fees, e.g `0.01` for 1%.
+    pub expected_return: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Leg<CurrencyT, PriceT, ExchangeIdT> {
+    pub exchange_id: ExchangeIdT,
+    pub from: CurrencyT,
+    pub to: CurrencyT,
+    /// The side of the book this leg trades against.
+    /// - [`Side::Ask`] buys the base currency with the quote currency.
+    /// - [`Side::Bid`] sells the base currency for the quote currency.
+    pub side: Side,
+    pub price: PriceT,
+}
+
+/// Find arbitrage cycles across `books`, most profitable first.
+///
+/// Each book is given as `(base, quote, finder)`, where prices in the `finder`
+/// are the amount of `quote` for one unit of `base`, e.g `("BTC", "USD", ..)`.
+///
+/// The same cycle is reported at most once.
+pub fn find_cycles<'a, CurrencyT, QuantityT, PriceT, ExchangeIdT, BuildHasherT>(
+    books: impl IntoIterator<
+        Item = (
+            CurrencyT,
+            CurrencyT,
+            &'a ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+        ),
+    >,
+) -> Vec<Cycle<CurrencyT, PriceT, ExchangeIdT>>
+where
+    CurrencyT: Eq + Hash + Clone,
+    QuantityT: ToPrimitive + 'a,
+    PriceT: Ord + Clone + ToPrimitive + 'a,
+    ExchangeIdT: Eq + Hash + Clone + 'a,
+    BuildHasherT: BuildHasher + 'a,
+{
+    let mut graph = Graph::default();
+    for (base, quote, finder) in books {
+        let (base, quote) = (graph.node(base), graph.node(quote));
+        for exchange_id in finder.exchanges() {
+            let taker = finder
+                .fees(exchange_id)
+                .and_then(|it| it.taker.to_f64())
+                .unwrap_or_default();
+            let book = finder.exchange(exchange_id);
+            if let Some((price, quantity)) = book.best_ask() {
+                if let (Some(p), Some(q)) = (price.to_f64(), quantity.to_f64()) {
+                    graph.edge(Edge {
+                        from: quote,
+                        to: base,
+                        rate: (1.0 - taker) / p,
+                        capacity: q * p,
+                        exchange_id: exchange_id.clone(),
+                        side: Side::Ask,
+                        price: price.clone(),
+                    })
+                }
+            }
+            if let Some((price, quantity)) = book.best_bid() {
+                if let (Some(p), Some(q)) = (price.to_f64(), quantity.to_f64()) {
+                    graph.edge(Edge {
+                        from: base,
+                        to: quote,
+                        rate: p * (1.0 - taker),
+                        capacity: q,
+                        exchange_id: exchange_id.clone(),
+                        side: Side::Bid,
+                        price: price.clone(),
+                    })
+                }
+            }
+        }
+    }
+    let mut cycles = graph
+        .negative_cycles()
+        .into_iter()
+        .map(|edges| graph.cycle(&edges))
+        .filter(|it| it.expected_return > 0.0)
+        .collect::<Vec<_>>();
+    cycles.sort_by(|left, right| right.expected_return.total_cmp(&left.expected_return));
+    cycles
+}
+
+struct Graph<CurrencyT, PriceT, ExchangeIdT> {
+    nodes: Vec<CurrencyT>,
+    ids: HashMap<CurrencyT, usize>,
+    edges: Vec<Edge<PriceT, ExchangeIdT>>,
+}
+
+struct Edge<PriceT, ExchangeIdT> {
+    from: usize,
+    to: usize,
+    /// Units of `to` received for each unit of `from`.
+    rate: f64,
+    /// In units of `from`.
+    capacity: f64,
+    exchange_id: ExchangeIdT,
+    side: Side,
+    price: PriceT,
+}
+
+impl<CurrencyT, PriceT, ExchangeIdT> Graph<CurrencyT, PriceT, ExchangeIdT>
+where
+    CurrencyT: Eq + Hash + Clone,
+    PriceT: Clone,
+    ExchangeIdT: Clone,
+{
+    fn node(&mut self, currency: CurrencyT) -> usize {
+        *self.ids.entry(currency.clone()).or_insert_with(|| {
+            self.nodes.push(currency);
+            self.nodes.len() - 1
+        })
+    }
+    fn edge(&mut self, edge: Edge<PriceT, ExchangeIdT>) {
+        // a non-positive rate would be a broken book, and has no logarithm
+        if edge.rate > 0.0 && edge.rate.is_finite() && edge.capacity > 0.0 {
+            self.edges.push(edge)
+        }
+    }
+    /// Each cycle is a list of edge indices, rotated to start at the lowest.
+    fn negative_cycles(&self) -> Vec<Vec<usize>> {
+        // a virtual source connected to every node means all cycles are reachable
+        let mut distance = vec![0.0; self.nodes.len()];
+        let mut predecessor = vec![None::<usize>; self.nodes.len()];
+        for _ in 1..self.nodes.len() {
+            let mut relaxed = false;
+            for (ix, edge) in self.edges.iter().enumerate() {
+                let candidate = distance[edge.from] - edge.rate.ln();
+                if candidate < distance[edge.to] - EPSILON {
+                    distance[edge.to] = candidate;
+                    predecessor[edge.to] = Some(ix);
+                    relaxed = true;
+                }
+            }
+            if !relaxed {
+                return vec![];
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut cycles = vec![];
+        for (ix, edge) in self.edges.iter().enumerate() {
+            if distance[edge.from] - edge.rate.ln() >= distance[edge.to] - EPSILON {
+                continue;
+            }
+            let mut predecessor = predecessor.clone();
+            predecessor[edge.to] = Some(ix);
+            // walking back far enough guarantees that we end up on the cycle
+            let mut node = edge.to;
+            for _ in 0..self.nodes.len() {
+                match predecessor[node] {
+                    Some(it) => node = self.edges[it].from,
+                    None => break,
+                }
+            }
+            let mut cycle = vec![];
+            let start = node;
+            while let Some(it) = predecessor[node] {
+                cycle.push(it);
+                node = self.edges[it].from;
+                if node == start || cycle.len() > self.nodes.len() {
+                    break;
+                }
+            }
+            if node != start || cycle.is_empty() {
+                continue;
+            }
+            cycle.reverse();
+            let lowest = cycle
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, it)| **it)
+                .map(|(pos, _)| pos)
+                .unwrap_or_default();
+            cycle.rotate_left(lowest);
+            if seen.insert(cycle.clone()) {
+                cycles.push(cycle)
+            }
+        }
+        cycles
+    }
+    fn cycle(&self, edges: &[usize]) -> Cycle<CurrencyT, PriceT, ExchangeIdT> {
+        let mut size = f64::INFINITY;
+        let mut growth = 1.0;
+        let mut legs = vec![];
+        for edge in edges.iter().map(|it| &self.edges[*it]) {
+            size = size.min(edge.capacity / growth);
+            growth *= edge.rate;
+            legs.push(Leg {
+                exchange_id: edge.exchange_id.clone(),
+                from: self.nodes[edge.from].clone(),
+                to: self.nodes[edge.to].clone(),
+                side: edge.side,
+                price: edge.price.clone(),
+            })
+        }
+        Cycle {
+            legs,
+            size,
+            expected_return: growth - 1.0,
+        }
+    }
+}
+
+impl<CurrencyT, PriceT, ExchangeIdT> Default for Graph<CurrencyT, PriceT, ExchangeIdT> {
+    fn default() -> Self {
+        Self {
+            nodes: Default::default(),
+            ids: Default::default(),
+            edges: Default::default(),
+        }
+    }
+}
+
+/// Ignore differences in log-space that are just floating point noise.
+const EPSILON: f64 = 1e-12;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Fees;
+
+    #[allow(non_camel_case_types)]
+    type u16f16 = fixed::FixedU32<typenum::U16>;
+
+    type Finder = ArbitrageFinder<u16f16, u16f16, &'static str>;
+
+    fn book(levels: [(Side, &str, &str); 2]) -> Finder {
+        let mut finder = Finder::default();
+        for (side, price, quantity) in levels {
+            let (price, quantity) = (u16f16::lit(price), u16f16::lit(quantity));
+            match side {
+                Side::Bid => drop(finder.buy("kraken", price, quantity).unwrap()),
+                Side::Ask => drop(finder.sell("kraken", price, quantity).unwrap()),
+            }
+        }
+        finder
+    }
+
+    #[test]
+    fn triangle() {
+        let btc_usd = book([(Side::Bid, "99", "1"), (Side::Ask, "100", "1")]);
+        let eth_btc = book([(Side::Bid, "0.05859375", "8"), (Side::Ask, "0.0625", "8")]);
+        let eth_usd = book([(Side::Bid, "7", "1"), (Side::Ask, "8", "1")]);
+
+        let cycles = find_cycles([
+            ("BTC", "USD", &btc_usd),
+            ("ETH", "BTC", &eth_btc),
+            ("ETH", "USD", &eth_usd),
+        ]);
+        let [Cycle {
+            legs,
+            size,
+            expected_return,
+        }] = &cycles[..]
+        else {
+            panic!("expected exactly one cycle, got {cycles:?}")
+        };
+        assert_eq!(
+            legs.iter()
+                .map(|it| (it.from, it.to, it.side))
+                .collect::<Vec<_>>(),
+            [
+                ("USD", "BTC", Side::Ask),
+                ("BTC", "ETH", Side::Ask),
+                ("ETH", "USD", Side::Bid),
+            ]
+        );
+        // 1 USD -> 0.01 BTC -> 0.16 ETH -> 1.12 USD
+        assert!((expected_return - 0.12).abs() < 1e-9);
+        // limited by the single ETH bid
+        assert!((size - 6.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fees_close_triangle() {
+        let btc_usd = book([(Side::Bid, "99", "1"), (Side::Ask, "100", "1")]);
+        let eth_btc = book([(Side::Bid, "0.05859375", "8"), (Side::Ask, "0.0625", "8")]);
+        let mut eth_usd = book([(Side::Bid, "7", "1"), (Side::Ask, "8", "1")]);
+        eth_usd.set_fees(
+            "kraken",
+            Fees {
+                maker: u16f16::ZERO,
+                taker: u16f16::lit("0.125"),
+            },
+        );
+        assert_eq!(
+            find_cycles([
+                ("BTC", "USD", &btc_usd),
+                ("ETH", "BTC", &eth_btc),
+                ("ETH", "USD", &eth_usd),
+            ]),
+            []
+        );
+    }
+}
@@ -1,23 +1,50 @@
 //! Exchange-specific integrations that expose a unified messaging interface.
 //!
 //! See submodules for protocol implementations and diagrams.
+
+// `tungstenite::Error` is large, but it's what the websocket layer gives us.
+#![allow(clippy::result_large_err)]
 use std::{
     fmt::{self, Display},
+    hash::{BuildHasher as _, Hasher as _, RandomState},
     io,
-    pin::pin,
+    pin::{pin, Pin},
+    str::FromStr,
+    time::Duration,
 };
 
 use bstr::BString;
-use futures::{future::Either, stream, Sink, SinkExt as _, Stream, TryStreamExt as _};
+use futures::{
+    future::Either, stream, Sink, SinkExt as _, Stream, StreamExt as _, TryStreamExt as _,
+};
 use io_extra::IoErrorExt as _;
+use num_traits::Zero;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_path_to_error::Path;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
-use tungstenite::client::IntoClientRequest;
+use tungstenite::client::IntoClientRequest as _;
 
 mod aevo;
+mod binance;
+mod book;
+mod bybit;
+mod coinbase;
+mod deribit;
 mod dydx;
+mod exchange;
+mod hyperliquid;
+mod keepalive;
+mod kraken;
+mod okx;
+mod proxy;
+
+pub use aevo::{Aevo, AevoState};
+pub use book::{BookError, OrderBook};
+pub use dydx::{Dydx, DydxState};
+pub use exchange::Exchange;
+pub use keepalive::Keepalive;
+pub use proxy::{Credentials, ParseProxyError, Proxy};
 
 type WsMessage = tungstenite::Message;
 type WsError = tungstenite::Error;
@@ -25,8 +52,128 @@ type WsResult<T> = tungstenite::Result<T>;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum ExchangeMessage<PriceT, QuantityT> {
-    Buy { price: PriceT, quantity: QuantityT },
-    Sell { price: PriceT, quantity: QuantityT },
+    Buy {
+        price: PriceT,
+        quantity: QuantityT,
+    },
+    Sell {
+        price: PriceT,
+        quantity: QuantityT,
+    },
+    /// The stream has started over, with a fresh snapshot to follow.
+    ///
+    /// Every level previously received from this stream should be discarded.
+    Resynced,
+}
+
+/// Connection settings shared by every integration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub keepalive: Keepalive,
+    pub endpoint: Endpoint,
+    /// Connect through this proxy, rather than directly.
+    pub proxy: Option<Proxy>,
+    /// Fetch REST snapshots from here, rather than the preset for [`Self::endpoint`].
+    ///
+    /// Only [`binance`] needs snapshots, and queries e.g
+    /// `{rest_endpoint}?symbol=BTCUSDT&limit=1000` for a Binance-shaped `depth` response,
+    /// so this should be e.g `http://localhost:8080/api/v3/depth` for a local stand-in.
+    pub rest_endpoint: Option<String>,
+}
+
+/// Which deployment of a venue to connect to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Endpoint {
+    #[default]
+    Mainnet,
+    /// Fails with [`io::ErrorKind::Unsupported`] for venues without a public testnet.
+    Testnet,
+    /// A `ws://` or `wss://` URL to connect to as-is, e.g a local stand-in server.
+    ///
+    /// This replaces the whole URL, including any path or query the venue's presets have,
+    /// e.g `ws://localhost:8080/ws/btcusdt@depth@100ms` for [`binance`].
+    /// REST snapshots are fetched from [`Config::rest_endpoint`] if set,
+    /// and mainnet otherwise.
+    Url(String),
+}
+
+impl Endpoint {
+    /// Pick between a venue's presets.
+    fn resolve(&self, mainnet: &str, testnet: Option<&str>) -> WsResult<String> {
+        match (self, testnet) {
+            (Endpoint::Mainnet, _) => Ok(mainnet.into()),
+            (Endpoint::Testnet, Some(testnet)) => Ok(testnet.into()),
+            (Endpoint::Testnet, None) => Err(WsError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("no testnet for {mainnet}"),
+            ))),
+            (Endpoint::Url(url), _) => Ok(url.clone()),
+        }
+    }
+}
+
+/// `mainnet`, `testnet`, or a `ws://` or `wss://` URL.
+impl FromStr for Endpoint {
+    type Err = ParseEndpointError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Endpoint::Mainnet),
+            "testnet" => Ok(Endpoint::Testnet),
+            url if url.starts_with("ws://") || url.starts_with("wss://") => {
+                Ok(Endpoint::Url(url.into()))
+            }
+            _ => Err(ParseEndpointError),
+        }
+    }
+}
+
+/// See [`Endpoint`]'s [`FromStr`] implementation.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+#[error("invalid endpoint, expected `mainnet`, `testnet`, or a `ws://` or `wss://` URL")]
+pub struct ParseEndpointError;
+
+/// Errors with the feed itself, rather than the transport or the message format.
+///
+/// These are wrapped in a [`tungstenite::Error::Io`], see [`FeedError::of`].
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FeedError {
+    /// No data was received for the [`Keepalive::stale_after`] period.
+    #[error("no data received for {after:?}")]
+    Stale { after: Duration },
+    /// A sequence number skipped ahead, so some updates were missed.
+    #[error("expected sequence number {expected}, got {got}")]
+    Gap { expected: u64, got: u64 },
+}
+
+impl FeedError {
+    /// Find a [`FeedError`] in an error from one of the integration streams.
+    pub fn of(error: &WsError) -> Option<&Self> {
+        match error {
+            WsError::Io(it) => it.get_ref()?.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Stream `symbol`'s book from `exchange`, see [`Exchange::symbol`].
+pub fn subscribe<PriceT, QuantityT>(
+    exchange: impl Exchange<PriceT, QuantityT>,
+    symbol: impl Into<String>,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>> {
+    subscribe_with(exchange, symbol, Config::default())
+}
+
+/// [`subscribe`], with non-default [`Config`].
+pub fn subscribe_with<PriceT, QuantityT>(
+    exchange: impl Exchange<PriceT, QuantityT>,
+    symbol: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>> {
+    let testnet = exchange.testnet();
+    connect_websocket(&exchange.mainnet(), testnet.as_deref(), config, move |it| {
+        exchange::protocol(it, exchange, symbol.into())
+    })
 }
 
 /// `id` should be e.g `"BTC-USD"`
@@ -37,34 +184,518 @@ where
     PriceT: DeserializeOwned,
     QuantityT: DeserializeOwned,
 {
-    connect_websocket("wss://indexer.dydx.trade/v4/ws", move |it| {
-        dydx::protocol(it, id.into())
-    })
+    dydx_with(id, Config::default())
+}
+
+/// [`dydx`], with non-default [`Config`].
+pub fn dydx_with<PriceT, QuantityT>(
+    id: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    subscribe_with(Dydx, id, config)
 }
 
 /// `id` should be e.g `"BTC-PERP"`
 pub fn aevo<PriceT, QuantityT>(
-    id: impl Display,
+    id: impl Into<String>,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    aevo_with(id, Config::default())
+}
+
+/// [`aevo`], with non-default [`Config`].
+pub fn aevo_with<PriceT, QuantityT>(
+    id: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    subscribe_with(Aevo, id, config)
+}
+
+/// Which of Binance's markets to connect to, see [`binance`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BinanceMarket {
+    Spot,
+    UsdMFutures,
+}
+
+/// `symbol` should be e.g `"BTCUSDT"`.
+pub fn binance<PriceT, QuantityT>(
+    symbol: impl Display,
+    market: BinanceMarket,
 ) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
 where
     PriceT: DeserializeOwned,
     QuantityT: DeserializeOwned,
 {
-    connect_websocket("wss://ws.aevo.xyz", move |it| aevo::protocol(it, id))
+    binance_with(symbol, market, Config::default())
+}
+
+/// [`binance`], with non-default [`Config`].
+pub fn binance_with<PriceT, QuantityT>(
+    symbol: impl Display,
+    market: BinanceMarket,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    let symbol = symbol.to_string().to_uppercase();
+    let ((ws, rest), (testnet_ws, testnet_rest)) = match market {
+        BinanceMarket::Spot => (
+            (
+                "wss://stream.binance.com:9443/ws",
+                "https://api.binance.com/api/v3/depth",
+            ),
+            (
+                "wss://stream.testnet.binance.vision/ws",
+                "https://testnet.binance.vision/api/v3/depth",
+            ),
+        ),
+        BinanceMarket::UsdMFutures => (
+            (
+                "wss://fstream.binance.com/ws",
+                "https://fapi.binance.com/fapi/v1/depth",
+            ),
+            (
+                "wss://stream.binancefuture.com/ws",
+                "https://testnet.binancefuture.com/fapi/v1/depth",
+            ),
+        ),
+    };
+    let rest = match (&config.rest_endpoint, &config.endpoint) {
+        (Some(it), _) => it,
+        (None, Endpoint::Testnet) => testnet_rest,
+        // an `Endpoint::Url` only replaces the websocket
+        (None, Endpoint::Mainnet | Endpoint::Url(_)) => rest,
+    };
+    let snapshot = format!("{rest}?symbol={symbol}&limit=1000");
+    let proxy = config.proxy.clone();
+    let stream = format!("{}@depth@100ms", symbol.to_lowercase());
+    connect_websocket(
+        &format!("{ws}/{stream}"),
+        Some(&format!("{testnet_ws}/{stream}")),
+        config,
+        move |it| binance::protocol(it, market, get_json(snapshot, proxy)),
+    )
+}
+
+/// `symbol` should be e.g `"BTC/USD"`
+pub fn kraken<PriceT, QuantityT>(
+    symbol: impl Into<String>,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    kraken_with(symbol, Config::default())
+}
+
+/// [`kraken`], with non-default [`Config`].
+pub fn kraken_with<PriceT, QuantityT>(
+    symbol: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    connect_websocket("wss://ws.kraken.com/v2", None, config, move |it| {
+        kraken::protocol(it, symbol.into())
+    })
+}
+
+/// `product_id` should be e.g `"BTC-USD"`
+pub fn coinbase<PriceT, QuantityT>(
+    product_id: impl Into<String>,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    coinbase_with(product_id, Config::default())
+}
+
+/// [`coinbase`], with non-default [`Config`].
+pub fn coinbase_with<PriceT, QuantityT>(
+    product_id: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    connect_websocket(
+        "wss://advanced-trade-ws.coinbase.com",
+        None,
+        config,
+        move |it| coinbase::protocol(it, product_id.into()),
+    )
+}
+
+/// `inst_id` should be e.g `"BTC-USDT"`
+pub fn okx<PriceT, QuantityT>(
+    inst_id: impl Into<String>,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    okx_with(inst_id, Config::default())
+}
+
+/// [`okx`], with non-default [`Config`].
+pub fn okx_with<PriceT, QuantityT>(
+    inst_id: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    connect_websocket(
+        "wss://ws.okx.com:8443/ws/v5/public",
+        Some("wss://wspap.okx.com:8443/ws/v5/public?brokerId=9999"),
+        config,
+        move |it| okx::protocol(it, inst_id.into()),
+    )
+}
+
+/// How many levels of Bybit's book to subscribe to, see [`bybit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BybitDepth {
+    One,
+    Fifty,
+    TwoHundred,
+    FiveHundred,
+}
+
+impl BybitDepth {
+    pub fn levels(self) -> u16 {
+        match self {
+            BybitDepth::One => 1,
+            BybitDepth::Fifty => 50,
+            BybitDepth::TwoHundred => 200,
+            BybitDepth::FiveHundred => 500,
+        }
+    }
+}
+
+/// `symbol` should be a linear contract, e.g `"BTCUSDT"`.
+pub fn bybit<PriceT, QuantityT>(
+    symbol: impl Into<String>,
+    depth: BybitDepth,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    bybit_with(symbol, depth, Config::default())
+}
+
+/// [`bybit`], with non-default [`Config`].
+pub fn bybit_with<PriceT, QuantityT>(
+    symbol: impl Into<String>,
+    depth: BybitDepth,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    connect_websocket(
+        "wss://stream.bybit.com/v5/public/linear",
+        Some("wss://stream-testnet.bybit.com/v5/public/linear"),
+        config,
+        move |it| bybit::protocol(it, symbol.into(), depth),
+    )
+}
+
+/// `coin` should be e.g `"BTC"`.
+pub fn hyperliquid<PriceT, QuantityT>(
+    coin: impl Into<String>,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    hyperliquid_with(coin, Config::default())
+}
+
+/// [`hyperliquid`], with non-default [`Config`].
+pub fn hyperliquid_with<PriceT, QuantityT>(
+    coin: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    connect_websocket(
+        "wss://api.hyperliquid.xyz/ws",
+        Some("wss://api.hyperliquid-testnet.xyz/ws"),
+        config,
+        move |it| hyperliquid::protocol(it, coin.into()),
+    )
+}
+
+/// `instrument_name` should be e.g `"BTC-PERPETUAL"`.
+pub fn deribit<PriceT, QuantityT>(
+    instrument_name: impl Into<String>,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned + Zero,
+{
+    deribit_with(instrument_name, Config::default())
+}
+
+/// [`deribit`], with non-default [`Config`].
+pub fn deribit_with<PriceT, QuantityT>(
+    instrument_name: impl Into<String>,
+    config: Config,
+) -> impl Stream<Item = tungstenite::Result<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned + Zero,
+{
+    connect_websocket(
+        "wss://www.deribit.com/ws/api/v2",
+        Some("wss://test.deribit.com/ws/api/v2"),
+        config,
+        move |it| deribit::protocol(it, instrument_name.into()),
+    )
+}
+
+/// `GET` a JSON document over HTTPS, through the same `proxy` as the websocket.
+async fn get_json<T: DeserializeOwned>(url: String, proxy: Option<Proxy>) -> WsResult<T> {
+    let client = match proxy {
+        None => reqwest::Client::builder(),
+        Some(proxy) => {
+            let (url, credentials) = match proxy {
+                Proxy::HttpConnect { addr, credentials } => (format!("http://{addr}"), credentials),
+                Proxy::Socks5 {
+                    addr,
+                    credentials,
+                    remote_dns: true,
+                } => (format!("socks5h://{addr}"), credentials),
+                Proxy::Socks5 {
+                    addr,
+                    credentials,
+                    remote_dns: false,
+                } => (format!("socks5://{addr}"), credentials),
+            };
+            let proxy =
+                reqwest::Proxy::all(url).map_err(|e| WsError::Io(io::Error::invalid_input(e)))?;
+            reqwest::Client::builder().proxy(match credentials {
+                Some(Credentials { username, password }) => proxy.basic_auth(&username, &password),
+                None => proxy,
+            })
+        }
+    }
+    .build()
+    .map_err(|e| WsError::Io(io::Error::other(e)))?;
+    let body = async {
+        client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    }
+    .await
+    .map_err(|e| WsError::Io(io::Error::other(e)))?;
+    deserialize_json(&Either::Left(body.into()))
 }
 
 fn connect_websocket<F, S, T>(
-    to: impl IntoClientRequest + Unpin, // TODO(aatifsyed): make a PR to tokio-tungstenite to relax `Unpin` bound,
+    mainnet: &str,
+    testnet: Option<&str>,
+    Config {
+        keepalive,
+        endpoint,
+        proxy,
+        rest_endpoint: _,
+    }: Config,
     f: F,
 ) -> impl Stream<Item = Result<T, tungstenite::Error>>
 where
-    F: FnOnce(WebSocketStream<MaybeTlsStream<TcpStream>>) -> S,
+    F: FnOnce(keepalive::KeepaliveStream<WebSocketStream<MaybeTlsStream<TcpStream>>>) -> S,
     S: Stream<Item = Result<T, tungstenite::Error>>,
 {
+    let to = endpoint.resolve(mainnet, testnet);
     let mut f = Some(f);
-    stream::once(tokio_tungstenite::connect_async(to))
-        .map_ok(move |(st, _http)| f.take().expect("stream::once only yields once")(st))
-        .try_flatten()
+    stream::once(async move {
+        let Some(proxy) = proxy else {
+            return tokio_tungstenite::connect_async(to?).await;
+        };
+        let request = to?.into_client_request()?;
+        let uri = request.uri();
+        let host = uri.host().ok_or(tungstenite::error::UrlError::NoHostName)?;
+        let port = match (uri.port_u16(), uri.scheme_str()) {
+            (Some(it), _) => it,
+            (None, Some("wss")) => 443,
+            (None, Some("ws")) => 80,
+            (None, _) => return Err(tungstenite::error::UrlError::UnsupportedUrlScheme.into()),
+        };
+        let stream = proxy.connect(host, port).await?;
+        tokio_tungstenite::client_async_tls(request, stream).await
+    })
+    .map_ok(move |(st, _http)| {
+        f.take().expect("stream::once only yields once")(keepalive::KeepaliveStream::new(
+            st, keepalive,
+        ))
+    })
+    .try_flatten()
+}
+
+/// Delays between reconnection attempts, see [`reconnecting`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// Delay after the first failure.
+    pub initial: Duration,
+    /// Delays grow by this factor after each consecutive failure...
+    pub multiplier: f64,
+    /// ...up to this limit.
+    pub max: Duration,
+    /// The fraction of each delay to randomly shave off, in `0.0..=1.0`,
+    /// so that many clients don't reconnect in lockstep.
+    pub jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            multiplier: 2.0,
+            max: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl Backoff {
+    /// `failures` should be at least one.
+    fn delay(&self, failures: u32) -> Duration {
+        let Self {
+            initial,
+            multiplier,
+            max,
+            jitter,
+        } = *self;
+        let exp = multiplier.powi(failures.saturating_sub(1).try_into().unwrap_or(i32::MAX));
+        let delay = initial.as_secs_f64() * exp;
+        // Not a real RNG, but good enough to desynchronise reconnecting clients without a
+        // dependency: each `RandomState::new` gets fresh keys (seeded from the OS once per thread,
+        // then incremented), and SipHash of no input under those keys is well spread over `u64`.
+        // Nothing here needs to be unpredictable.
+        let noise = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let delay = delay.min(max.as_secs_f64()) * (1.0 - jitter.clamp(0.0, 1.0) * noise);
+        Duration::try_from_secs_f64(delay).unwrap_or(max)
+    }
+}
+
+/// Re-run `connect` (including its handshake and subscription) whenever its stream
+/// ends or errors, waiting according to `backoff` between attempts.
+///
+/// Errors are passed through.
+/// Once a new connection yields its first message, it is preceded by
+/// [`ExchangeMessage::Resynced`].
+pub fn reconnecting<F, S, PriceT, QuantityT>(
+    connect: F,
+    backoff: Backoff,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    F: FnMut() -> S,
+    S: Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>,
+{
+    struct State<F, S, T> {
+        connect: F,
+        backoff: Backoff,
+        current: Option<Pin<Box<S>>>,
+        /// Consecutive failures.
+        failures: u32,
+        /// Whether the current stream is a reconnection that hasn't yielded yet.
+        resyncing: bool,
+        /// Held back while we yield [`ExchangeMessage::Resynced`].
+        buffered: Option<T>,
+        started: bool,
+    }
+    stream::unfold(
+        State {
+            connect,
+            backoff,
+            current: None,
+            failures: 0,
+            resyncing: false,
+            buffered: None,
+            started: false,
+        },
+        |mut state| async move {
+            if let Some(it) = state.buffered.take() {
+                return Some((Ok(it), state));
+            }
+            loop {
+                let Some(current) = &mut state.current else {
+                    if state.failures > 0 {
+                        tokio::time::sleep(state.backoff.delay(state.failures)).await;
+                    }
+                    state.current = Some(Box::pin((state.connect)()));
+                    state.resyncing = state.started;
+                    state.started = true;
+                    continue;
+                };
+                match current.next().await {
+                    Some(Ok(it)) => {
+                        state.failures = 0;
+                        match state.resyncing {
+                            true => {
+                                state.resyncing = false;
+                                state.buffered = Some(it);
+                                return Some((Ok(ExchangeMessage::Resynced), state));
+                            }
+                            false => return Some((Ok(it), state)),
+                        }
+                    }
+                    Some(Err(e)) => {
+                        state.current = None;
+                        state.failures = state.failures.saturating_add(1);
+                        return Some((Err(e), state));
+                    }
+                    None => {
+                        state.current = None;
+                        state.failures = state.failures.saturating_add(1);
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Bids then asks, as deltas.
+fn orders2exchangemessages<PriceT, QuantityT>(
+    bids: Vec<(PriceT, QuantityT)>,
+    asks: Vec<(PriceT, QuantityT)>,
+) -> Vec<ExchangeMessage<PriceT, QuantityT>> {
+    let bids = bids
+        .into_iter()
+        .map(|(price, quantity)| ExchangeMessage::Buy { price, quantity });
+    let asks = asks
+        .into_iter()
+        .map(|(price, quantity)| ExchangeMessage::Sell { price, quantity });
+    bids.chain(asks).collect()
 }
 
 async fn send_json(s: impl Sink<WsMessage, Error = WsError>, t: impl Serialize) -> WsResult<()> {
@@ -73,12 +704,18 @@ async fn send_json(s: impl Sink<WsMessage, Error = WsError>, t: impl Serialize)
 }
 
 async fn recv_json<T: DeserializeOwned>(s: impl Stream<Item = WsResult<WsMessage>>) -> WsResult<T> {
+    deserialize_json(&recv_raw(s).await?)
+}
+
+/// Receive the next data message, for when it needs to be deserialized more than once.
+async fn recv_raw(s: impl Stream<Item = WsResult<WsMessage>>) -> WsResult<Either<Vec<u8>, String>> {
     let mut s = pin!(s);
-    let message = loop {
+    Ok(loop {
         match s.try_next().await {
             Ok(Some(WsMessage::Binary(it))) => break Either::Left(it),
             Ok(Some(WsMessage::Text(it))) => break Either::Right(it),
-            Ok(Some(WsMessage::Ping(_) | WsMessage::Pong(_))) => continue, // TODO(aatifsyed): do we need to respond to pings manually?
+            // tungstenite queues pongs, and `KeepaliveStream` makes sure they're flushed
+            Ok(Some(WsMessage::Ping(_) | WsMessage::Pong(_))) => continue,
             Ok(Some(WsMessage::Frame(_))) => continue, // TODO(aatifsyed): is this unreachable?
             Ok(Some(WsMessage::Close(_)) | None) => {
                 return Err(WsError::Io(io::Error::unexpected_eof(
@@ -87,8 +724,7 @@ async fn recv_json<T: DeserializeOwned>(s: impl Stream<Item = WsResult<WsMessage
             }
             Err(e) => return Err(e),
         };
-    };
-    deserialize_json(&message)
+    })
 }
 
 fn deserialize_json<'a, T: Deserialize<'a>>(
@@ -122,6 +758,53 @@ fn deserialize_json<'a, T: Deserialize<'a>>(
     })
 }
 
+/// A JSON number, or a number in a JSON string, deserialized through its decimal text.
+///
+/// Some exchanges send prices as numbers rather than strings, which e.g [`fixed`]
+/// refuses to deserialize from.
+/// The text is kept for checksums.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct JsonNumber<T> {
+    /// Without quotes.
+    text: String,
+    value: T,
+    /// Whether this was sent as a string, so that it serializes as one.
+    quoted: bool,
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for JsonNumber<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error as _, IntoDeserializer as _};
+        let raw = Box::<serde_json::value::RawValue>::deserialize(deserializer)?;
+        let (text, quoted) = match raw.get().starts_with('"') {
+            true => (
+                serde_json::from_str::<String>(raw.get()).map_err(D::Error::custom)?,
+                true,
+            ),
+            false => (raw.get().to_owned(), false),
+        };
+        let value = T::deserialize(text.clone().into_deserializer())
+            .map_err(|e: serde::de::value::Error| D::Error::custom(e))?;
+        Ok(Self {
+            text,
+            value,
+            quoted,
+        })
+    }
+}
+
+impl<T> Serialize for JsonNumber<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error as _;
+        if self.quoted {
+            return self.text.serialize(serializer);
+        }
+        serde_json::value::RawValue::from_string(self.text.clone())
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 struct SerializationError {
     path: Path,
@@ -171,3 +854,233 @@ where
     let json2repr = serde_json::from_value(json).unwrap();
     assert_eq!(repr, json2repr);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reconnect() {
+        let mut connections = 0;
+        let messages = reconnecting(
+            || {
+                connections += 1;
+                stream::iter(match connections {
+                    1 => vec![
+                        Ok(ExchangeMessage::Buy {
+                            price: 1,
+                            quantity: 1,
+                        }),
+                        Err(WsError::ConnectionClosed),
+                    ],
+                    2 => vec![],
+                    _ => vec![Ok(ExchangeMessage::Sell {
+                        price: 2,
+                        quantity: 2,
+                    })],
+                })
+            },
+            Backoff {
+                initial: Duration::ZERO,
+                ..Default::default()
+            },
+        );
+        let messages = messages
+            .take(5)
+            .map(|it| it.map_err(|e| e.to_string()))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            messages,
+            [
+                Ok(ExchangeMessage::Buy {
+                    price: 1,
+                    quantity: 1
+                }),
+                Err(WsError::ConnectionClosed.to_string()),
+                Ok(ExchangeMessage::Resynced),
+                Ok(ExchangeMessage::Sell {
+                    price: 2,
+                    quantity: 2
+                }),
+                Ok(ExchangeMessage::Resynced),
+            ]
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            multiplier: 2.0,
+            max: Duration::from_secs(10),
+            jitter: 0.0,
+        };
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(3), Duration::from_secs(4));
+        assert_eq!(backoff.delay(100), Duration::from_secs(10));
+
+        let jittered = Backoff {
+            jitter: 0.5,
+            ..backoff
+        }
+        .delay(2);
+        assert!(jittered >= Duration::from_secs(1) && jittered <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn endpoint() {
+        let resolve = |it: &str, testnet| {
+            it.parse::<Endpoint>()
+                .unwrap()
+                .resolve("wss://main", testnet)
+                .map_err(|e| match e {
+                    WsError::Io(e) => e.kind(),
+                    _ => unreachable!(),
+                })
+        };
+        assert_eq!(resolve("mainnet", None).unwrap(), "wss://main");
+        assert_eq!(
+            resolve("testnet", Some("wss://test")).unwrap(),
+            "wss://test"
+        );
+        assert_eq!(
+            resolve("testnet", None).unwrap_err(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            resolve("ws://localhost:8080", Some("wss://test")).unwrap(),
+            "ws://localhost:8080"
+        );
+        assert_eq!("tesnet".parse::<Endpoint>(), Err(ParseEndpointError));
+        assert_eq!(
+            "localhost:8080".parse::<Endpoint>(),
+            Err(ParseEndpointError)
+        );
+    }
+
+    #[tokio::test]
+    async fn proxied() {
+        use tokio::{
+            io::{AsyncBufReadExt as _, AsyncWriteExt as _},
+            net::{TcpListener, TcpStream},
+        };
+
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", server.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = server.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            ws.send(WsMessage::Text("hello".into())).await.unwrap();
+            futures::future::pending::<()>().await
+        });
+
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = proxy.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = proxy.accept().await.unwrap();
+            let mut stream = tokio::io::BufReader::new(stream);
+            let mut target = None;
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                match line.strip_prefix("CONNECT ") {
+                    Some(rest) => target = rest.split_whitespace().next().map(String::from),
+                    None if line == "\r\n" => break,
+                    None => {}
+                }
+            }
+            let mut upstream = TcpStream::connect(target.unwrap()).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+            tokio::io::copy_bidirectional(&mut stream, &mut upstream)
+                .await
+                .unwrap();
+        });
+
+        let config = Config {
+            proxy: Some(format!("http://{addr}").parse().unwrap()),
+            ..Default::default()
+        };
+        let mut messages = pin!(connect_websocket(&url, None, config, |it| it));
+        assert_eq!(
+            messages.next().await.unwrap().unwrap(),
+            WsMessage::Text("hello".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn binance_local() {
+        use tokio::{
+            io::{AsyncBufReadExt as _, AsyncWriteExt as _},
+            net::TcpListener,
+        };
+
+        let ws = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_url = format!("ws://{}/ws/btcusdt@depth@100ms", ws.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = ws.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let update = serde_json::json!({"U": 8, "u": 9, "b": [], "a": [["2", "1"]]});
+            ws.send(WsMessage::Text(update.to_string())).await.unwrap();
+            futures::future::pending::<()>().await
+        });
+
+        let rest = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rest_url = format!("http://{}/api/v3/depth", rest.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = rest.accept().await.unwrap();
+            let mut stream = tokio::io::BufReader::new(stream);
+            let mut request_line = String::new();
+            stream.read_line(&mut request_line).await.unwrap();
+            assert!(
+                request_line.starts_with("GET /api/v3/depth?symbol=BTCUSDT&limit=1000 "),
+                "{request_line}"
+            );
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let body = serde_json::json!({"lastUpdateId": 7, "bids": [["1", "1"]], "asks": []});
+            let body = body.to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            futures::future::pending::<()>().await
+        });
+
+        let messages = binance_with::<u16f16, u16f16>(
+            "btcusdt",
+            BinanceMarket::Spot,
+            Config {
+                endpoint: Endpoint::Url(ws_url),
+                rest_endpoint: Some(rest_url),
+                ..Default::default()
+            },
+        )
+        .take(2)
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+        assert_eq!(
+            messages,
+            [
+                ExchangeMessage::Buy {
+                    price: u16f16::lit("1"),
+                    quantity: u16f16::lit("1")
+                },
+                ExchangeMessage::Sell {
+                    price: u16f16::lit("2"),
+                    quantity: u16f16::lit("1")
+                },
+            ]
+        );
+    }
+}
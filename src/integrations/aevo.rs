@@ -1,27 +1,21 @@
 //! Most of the comments in [`dydx`](crate::integrations::dydx) also apply here.
 
-use std::{fmt::Display, io};
+use std::io;
 
-use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use futures::{future::Either, Sink, Stream};
 use io_extra::IoErrorExt as _;
+use num_traits::Zero;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
+use tracing::warn;
 
-use super::{bail, recv_json, send_json, ExchangeMessage, WsError, WsMessage, WsResult};
-
-/// Input channel should NOT have had messages sent over it...
-/// `id` should be e.g `BTC-PERP`.
-pub fn protocol<PriceT, QuantityT>(
-    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
-    id: impl Display,
-) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
-where
-    PriceT: DeserializeOwned,
-    QuantityT: DeserializeOwned,
-{
-    stream::once(_protocol(s, id)).flatten()
-}
+use super::{
+    deserialize_json, orders2exchangemessages, send_json, Exchange, ExchangeMessage, OrderBook,
+    WsError, WsMessage, WsResult,
+};
 
+/// [Aevo](https://api-docs.aevo.xyz/reference/orderbook).
+///
 /// Note that aevo documents a 15 minute timeout, but I've not seen this in practice.
 ///
 /// ```text
@@ -54,67 +48,106 @@ where
 ///                     └───┘          └────────┘                    
 /// ```
 /// <https://www.plantuml.com/plantuml/uml/POv12WCn24NtEOKNADrtKUOgoOoT4MOqH8KUloObYz90NFp_dhYevMP-dQc8mUq9-5wFp3i-GBtesgXWcbdl0ukgUYDnXGjLyuxf5Ab0_28cmmJn_XtELG-nqGx-Iz-zRjbGH_vhJhKJSorlgVybHbpz0G00>
-async fn _protocol<PriceT, QuantityT>(
-    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
-    id: impl Display,
-) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+///
+/// Later snapshots are checked against the book built up from the previous
+/// snapshot and updates, and any differences are emitted as corrections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Aevo;
+
+impl<PriceT, QuantityT> Exchange<PriceT, QuantityT> for Aevo
 where
-    PriceT: DeserializeOwned,
-    QuantityT: DeserializeOwned,
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
 {
-    if let Err(e) = send_json(
-        &mut s,
-        json!({"op": "subscribe", "data": [format!("orderbook:{id}")]}),
-    )
-    .await
+    type State = AevoState<PriceT, QuantityT>;
+    fn mainnet(&self) -> String {
+        "wss://ws.aevo.xyz".into()
+    }
+    fn testnet(&self) -> Option<String> {
+        Some("wss://ws-testnet.aevo.xyz".into())
+    }
+    /// Perpetuals are always quoted in USD.
+    fn symbol(&self, base: &str, _quote: &str) -> String {
+        format!("{base}-PERP")
+    }
+    async fn handshake<S>(&self, s: &mut S, symbol: &str) -> WsResult<Self::State>
+    where
+        S: Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
     {
-        bail!(e)
-    };
-    match recv_json(&mut s).await {
-        Ok(Data {
-            data: DataInner::Snapshot { bids, asks },
-        }) => match recv_json::<Data<Vec<String>>>(&mut s).await {
-            Ok(_spurious) => {
-                let initial_snapshot = orders2exchangemessages(bids, asks).map(Ok);
-                let remaining_updates = stream::try_unfold(s, |mut it| async move {
-                    match recv_json(&mut it).await {
-                        Ok(Data {
-                            data: DataInner::Update { bids, asks },
-                        }) => Ok(Some((
-                            Either::Left(orders2exchangemessages(bids, asks).map(Ok)),
-                            it,
-                        ))),
-                        Ok(Data {
-                            data: DataInner::Snapshot { .. },
-                        }) => Ok(Some((Either::Right(stream::empty()), it))), // TODO(aatifsyed): could check that snapshot matches here
-                        Err(e) => Err(e),
-                    }
-                })
-                .try_flatten();
-                Either::Right(initial_snapshot.chain(remaining_updates))
+        send_json(
+            s,
+            json!({"op": "subscribe", "data": [format!("orderbook:{symbol}")]}),
+        )
+        .await?;
+        Ok(AevoState {
+            book: OrderBook::default(),
+            phase: Phase::AwaitingSnapshot,
+        })
+    }
+    fn decode(
+        &self,
+        AevoState { book, phase }: &mut Self::State,
+        raw: &Either<Vec<u8>, String>,
+    ) -> WsResult<Vec<ExchangeMessage<PriceT, QuantityT>>> {
+        Ok(match phase {
+            Phase::AwaitingSnapshot => match deserialize_json(raw)? {
+                Data {
+                    data: DataInner::Snapshot { bids, asks },
+                } => {
+                    *phase = Phase::AwaitingSpurious;
+                    book.apply_snapshot(bids, asks)
+                }
+                Data { .. } => {
+                    return Err(WsError::Io(io::Error::invalid_data(
+                        r#"expected to receive "snapshot""#,
+                    )))
+                }
+            },
+            Phase::AwaitingSpurious => {
+                let Data { data: _spurious } = deserialize_json::<Data<Vec<String>>>(raw)?;
+                *phase = Phase::Streaming;
+                vec![]
             }
-            Err(e) => bail!(e),
-        },
-        Ok(_) => bail!(io::Error::invalid_data(r#"expected to receive "snapshot""#)),
-        Err(e) => bail!(e),
+            Phase::Streaming => match deserialize_json(raw)? {
+                Data {
+                    data: DataInner::Update { bids, asks },
+                } => {
+                    let mut messages = orders2exchangemessages(bids, asks);
+                    book.apply_all(&mut messages);
+                    messages
+                }
+                Data {
+                    data: DataInner::Snapshot { bids, asks },
+                } => {
+                    let corrections = book.apply_snapshot(bids, asks);
+                    if book.validate().is_err() {
+                        warn!("snapshot crossed the book")
+                    }
+                    if !corrections.is_empty() {
+                        warn!(
+                            corrections = corrections.len(),
+                            "snapshot diverged from local book"
+                        )
+                    }
+                    corrections
+                }
+            },
+        })
     }
 }
 
-fn orders2exchangemessages<PriceT, QuantityT>(
-    bids: Vec<(PriceT, QuantityT)>,
-    asks: Vec<(PriceT, QuantityT)>,
-) -> impl Stream<Item = ExchangeMessage<PriceT, QuantityT>>
-where
-    PriceT: DeserializeOwned,
-    QuantityT: DeserializeOwned,
-{
-    let bids = bids
-        .into_iter()
-        .map(|(price, quantity)| ExchangeMessage::Buy { price, quantity });
-    let asks = asks
-        .into_iter()
-        .map(|(price, quantity)| ExchangeMessage::Sell { price, quantity });
-    stream::iter(bids.chain(asks))
+/// What [`Aevo`] carries between messages, see [`Exchange::State`].
+#[derive(Debug)]
+pub struct AevoState<PriceT, QuantityT> {
+    book: OrderBook<PriceT, QuantityT>,
+    phase: Phase,
+}
+
+#[derive(Debug)]
+enum Phase {
+    AwaitingSnapshot,
+    AwaitingSpurious,
+    Streaming,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
@@ -0,0 +1,362 @@
+//! Binance's [diff. depth stream](https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#how-to-manage-a-local-order-book-correctly)
+//! only carries deltas, so must be stitched onto a REST snapshot using update ids.
+//!
+//! Spot and USD-M futures differ in how the update ids chain together, see [`Stitch`].
+
+use std::{future::Future, io, pin::pin};
+
+use futures::{
+    future::{self, Either},
+    stream, Sink, Stream, StreamExt as _, TryStreamExt as _,
+};
+use io_extra::IoErrorExt as _;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    bail, orders2exchangemessages, recv_json, BinanceMarket, ExchangeMessage, FeedError, WsError,
+    WsMessage, WsResult,
+};
+
+/// Input channel should already be connected to a `<symbol>@depth` stream.
+///
+/// `snapshot` is only polled after the connection is established,
+/// and updates are buffered until it resolves.
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    market: BinanceMarket,
+    snapshot: impl Future<Output = WsResult<Snapshot<PriceT, QuantityT>>>,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    stream::once(_protocol(s, market, snapshot)).flatten()
+}
+
+/// ```text
+///           ┌───┐                ┌────────┐
+///           │bot│                │exchange│
+///           └─┬─┘                └───┬────┘
+///             │       connect        │
+///             │─────────────────────>│
+///             │                      │
+///             │   GET /depth (REST)  │
+///             │─────────────────────>│
+///             │                      │
+/// ╔═══════╤═══╪══════════════════════╪═══╗
+/// ║ LOOP  │  until snapshot          │   ║
+/// ╟───────┘   │   update (buffered)  │   ║
+/// ║           │<─ ─ ─ ─ ─ ─ ─ ─ ─ ─ ─│   ║
+/// ╚═══════════╪══════════════════════╪═══╝
+///             │       snapshot       │
+///             │<─────────────────────│
+///             │                      │
+/// ╔═══════╤═══╪══════════════════════╪═══╗
+/// ║ LOOP  │  infinite                │   ║
+/// ╟───────┘   │        update        │   ║
+/// ║           │<─ ─ ─ ─ ─ ─ ─ ─ ─ ─ ─│   ║
+/// ╚═══════════╪══════════════════════╪═══╝
+///           ┌─┴─┐                ┌───┴────┐
+///           │bot│                │exchange│
+///           └───┘                └────────┘
+/// ```
+async fn _protocol<PriceT, QuantityT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    market: BinanceMarket,
+    snapshot: impl Future<Output = WsResult<Snapshot<PriceT, QuantityT>>>,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    let mut snapshot = pin!(snapshot);
+    let mut buffered = vec![];
+    let Snapshot {
+        last_update_id,
+        bids,
+        asks,
+    } = loop {
+        match future::select(snapshot.as_mut(), pin!(recv_json(&mut s))).await {
+            Either::Left((Ok(it), _)) => break it,
+            Either::Left((Err(e), _)) => bail!(e),
+            Either::Right((Ok(it), _)) => buffered.push(it),
+            Either::Right((Err(e), _)) => bail!(e),
+        }
+    };
+
+    let mut stitch = Stitch::new(market, last_update_id);
+    let mut initial = orders2exchangemessages(bids, asks);
+    for update in buffered {
+        match stitch.check(&update) {
+            Ok(true) => initial.extend(orders2exchangemessages(update.bids, update.asks)),
+            Ok(false) => {}
+            Err(e) => bail!(e),
+        }
+    }
+
+    let cont = stream::try_unfold((s, stitch), |(mut it, mut stitch)| async move {
+        loop {
+            let update = recv_json::<DepthUpdate<PriceT, QuantityT>>(&mut it).await?;
+            if stitch.check(&update)? {
+                let messages = orders2exchangemessages(update.bids, update.asks);
+                break Ok::<_, WsError>(Some((
+                    stream::iter(messages.into_iter().map(Ok)),
+                    (it, stitch),
+                )));
+            }
+        }
+    })
+    .try_flatten();
+    Either::Right(stream::iter(initial.into_iter().map(Ok)).chain(cont))
+}
+
+/// Decides which updates follow on from a snapshot, and catches gaps after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stitch {
+    market: BinanceMarket,
+    /// The `lastUpdateId` of the snapshot, then the `u` of the last applied update.
+    last: u64,
+    started: bool,
+}
+
+impl Stitch {
+    fn new(market: BinanceMarket, last_update_id: u64) -> Self {
+        Self {
+            market,
+            last: last_update_id,
+            started: false,
+        }
+    }
+    /// Whether `update` should be applied.
+    ///
+    /// Updates from before the snapshot are skipped.
+    fn check<PriceT, QuantityT>(
+        &mut self,
+        DepthUpdate {
+            first_update_id,
+            final_update_id,
+            previous_final_update_id,
+            ..
+        }: &DepthUpdate<PriceT, QuantityT>,
+    ) -> WsResult<bool> {
+        let gap = |expected, got| {
+            Err(WsError::Io(io::Error::invalid_data(FeedError::Gap {
+                expected,
+                got,
+            })))
+        };
+        let Self {
+            market,
+            last,
+            started,
+        } = *self;
+        match (market, started) {
+            (BinanceMarket::Spot, false) => {
+                if *final_update_id <= last {
+                    return Ok(false);
+                }
+                if *first_update_id > last + 1 {
+                    return gap(last + 1, *first_update_id);
+                }
+            }
+            (BinanceMarket::Spot, true) => {
+                if *first_update_id != last + 1 {
+                    return gap(last + 1, *first_update_id);
+                }
+            }
+            (BinanceMarket::UsdMFutures, false) => {
+                if *final_update_id < last {
+                    return Ok(false);
+                }
+                if *first_update_id > last {
+                    return gap(last, *first_update_id);
+                }
+            }
+            (BinanceMarket::UsdMFutures, true) => match previous_final_update_id {
+                Some(it) if *it == last => {}
+                Some(it) => return gap(last, *it),
+                None => {
+                    return Err(WsError::Io(io::Error::invalid_data(
+                        r#"expected "pu" on a futures update"#,
+                    )))
+                }
+            },
+        }
+        self.last = *final_update_id;
+        self.started = true;
+        Ok(true)
+    }
+}
+
+/// The response to `GET /api/v3/depth` or `GET /fapi/v1/depth`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot<PriceT, QuantityT> {
+    last_update_id: u64,
+    bids: Vec<(PriceT, QuantityT)>,
+    asks: Vec<(PriceT, QuantityT)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+struct DepthUpdate<PriceT, QuantityT> {
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    /// Futures only.
+    #[serde(rename = "pu", default, skip_serializing_if = "Option::is_none")]
+    previous_final_update_id: Option<u64>,
+    #[serde(rename = "b")]
+    bids: Vec<(PriceT, QuantityT)>,
+    #[serde(rename = "a")]
+    asks: Vec<(PriceT, QuantityT)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt as _;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::integrations::{round_trip, u16f16};
+
+    fn update(first: u64, last: u64, previous: Option<u64>) -> DepthUpdate<(), ()> {
+        DepthUpdate {
+            first_update_id: first,
+            final_update_id: last,
+            previous_final_update_id: previous,
+            bids: vec![],
+            asks: vec![],
+        }
+    }
+
+    #[test]
+    fn deser() {
+        round_trip(
+            Snapshot {
+                last_update_id: 1027024,
+                bids: vec![(u16f16::lit("4"), u16f16::lit("431"))],
+                asks: vec![],
+            },
+            json!({"lastUpdateId": 1027024, "bids": [["4", "431"]], "asks": []}),
+        );
+        round_trip(
+            DepthUpdate {
+                first_update_id: 157,
+                final_update_id: 160,
+                previous_final_update_id: Some(156),
+                bids: vec![(u16f16::lit("0.0625"), u16f16::lit("10"))],
+                asks: vec![(u16f16::lit("0.125"), u16f16::lit("0"))],
+            },
+            json!({"U": 157, "u": 160, "pu": 156, "b": [["0.0625", "10"]], "a": [["0.125", "0"]]}),
+        );
+    }
+
+    #[test]
+    fn stitch_spot() {
+        let mut stitch = Stitch::new(BinanceMarket::Spot, 100);
+        assert!(!stitch.check(&update(90, 100, None)).unwrap());
+        assert!(stitch.check(&update(95, 105, None)).unwrap());
+        assert!(stitch.check(&update(106, 110, None)).unwrap());
+        let gap = stitch.check(&update(112, 115, None)).unwrap_err();
+        assert_eq!(
+            FeedError::of(&gap),
+            Some(&FeedError::Gap {
+                expected: 111,
+                got: 112
+            })
+        );
+
+        // the snapshot is newer than the first update we have
+        let gap = Stitch::new(BinanceMarket::Spot, 100).check(&update(102, 105, None));
+        assert!(gap.is_err());
+    }
+
+    #[test]
+    fn stitch_futures() {
+        let mut stitch = Stitch::new(BinanceMarket::UsdMFutures, 100);
+        assert!(!stitch.check(&update(90, 99, Some(89))).unwrap());
+        assert!(stitch.check(&update(100, 105, Some(99))).unwrap());
+        assert!(stitch.check(&update(108, 110, Some(105))).unwrap());
+        let gap = stitch.check(&update(112, 115, Some(111))).unwrap_err();
+        assert_eq!(
+            FeedError::of(&gap),
+            Some(&FeedError::Gap {
+                expected: 110,
+                got: 111
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn buffers_until_snapshot() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (snapshot_tx, snapshot_rx) = futures::channel::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for update in [
+                json!({"U": 1, "u": 5, "b": [["1", "1"]], "a": []}),
+                json!({"U": 6, "u": 10, "b": [["2", "1"]], "a": []}),
+                json!({"U": 11, "u": 12, "b": [], "a": [["3", "1"]]}),
+            ] {
+                ws.send(WsMessage::Text(update.to_string())).await.unwrap();
+            }
+            // only answer the snapshot request once everything is buffered
+            snapshot_tx.send(()).unwrap();
+            ws.send(WsMessage::Text(
+                json!({"U": 13, "u": 13, "b": [], "a": [["4", "1"]]}).to_string(),
+            ))
+            .await
+            .unwrap();
+            std::future::pending::<()>().await;
+        });
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let messages = protocol(ws, BinanceMarket::Spot, async move {
+            snapshot_rx.await.unwrap();
+            Ok(Snapshot {
+                last_update_id: 7,
+                bids: vec![(u16f16::lit("0"), u16f16::lit("1"))],
+                asks: vec![],
+            })
+        })
+        .take(4)
+        .map_ok(|it| match it {
+            ExchangeMessage::Buy { price, quantity } => ExchangeMessage::Buy {
+                price: price.to_num::<u32>(),
+                quantity: quantity.to_num::<u32>(),
+            },
+            ExchangeMessage::Sell { price, quantity } => ExchangeMessage::Sell {
+                price: price.to_num(),
+                quantity: quantity.to_num(),
+            },
+            ExchangeMessage::Resynced => ExchangeMessage::Resynced,
+        })
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+        assert_eq!(
+            messages,
+            [
+                ExchangeMessage::Buy {
+                    price: 0,
+                    quantity: 1
+                },
+                ExchangeMessage::Buy {
+                    price: 2,
+                    quantity: 1
+                },
+                ExchangeMessage::Sell {
+                    price: 3,
+                    quantity: 1
+                },
+                ExchangeMessage::Sell {
+                    price: 4,
+                    quantity: 1
+                },
+            ]
+        );
+    }
+}
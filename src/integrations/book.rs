@@ -0,0 +1,328 @@
+//! Rebuild a single exchange's book from its feed, for venues whose messages
+//! can't be checked on their own, e.g to diff successive snapshots, or
+//! to compute checksums over the top of the book.
+
+use std::{collections::BTreeMap, mem};
+
+use itertools::Either;
+use num_traits::Zero;
+use tracing::warn;
+
+use super::ExchangeMessage;
+use crate::Side;
+
+/// A single exchange's book, rebuilt from its [`ExchangeMessage`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OrderBook<PriceT, QuantityT> {
+    bids: BTreeMap<PriceT, QuantityT>,
+    asks: BTreeMap<PriceT, QuantityT>,
+}
+
+/// An [`ExchangeMessage`] that left an [`OrderBook`] in an unexpected state,
+/// usually a sign of missed or reordered messages.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum BookError<PriceT> {
+    /// The best bid is at or above the best ask.
+    ///
+    /// The message is still applied.
+    #[error("book is crossed, with a bid at {bid:?} and an ask at {ask:?}")]
+    Crossed { bid: PriceT, ask: PriceT },
+    /// The message is ignored.
+    #[error("negative quantity at {price:?} on the {side:?} side")]
+    Negative { side: Side, price: PriceT },
+    /// The exchange needlessly stated that a price level was empty,
+    /// see [`Error::Needless`](crate::Error::Needless).
+    #[error("needless removal of {price:?} on the {side:?} side")]
+    Needless { side: Side, price: PriceT },
+}
+
+impl<PriceT, QuantityT> OrderBook<PriceT, QuantityT>
+where
+    PriceT: Ord + Clone,
+    QuantityT: Zero + PartialOrd + Clone,
+{
+    /// Apply a delta, where a zero quantity removes the level.
+    ///
+    /// [`ExchangeMessage::Resynced`] clears the book.
+    pub fn apply(
+        &mut self,
+        message: ExchangeMessage<PriceT, QuantityT>,
+    ) -> Result<(), BookError<PriceT>> {
+        let (side, price, quantity) = match message {
+            ExchangeMessage::Buy { price, quantity } => (Side::Bid, price, quantity),
+            ExchangeMessage::Sell { price, quantity } => (Side::Ask, price, quantity),
+            ExchangeMessage::Resynced => {
+                *self = Self::default();
+                return Ok(());
+            }
+        };
+        if quantity < QuantityT::zero() {
+            return Err(BookError::Negative { side, price });
+        }
+        let levels = self.side_mut(side);
+        match quantity.is_zero() {
+            true => {
+                if levels.remove(&price).is_none() {
+                    return Err(BookError::Needless { side, price });
+                }
+            }
+            false => drop(levels.insert(price, quantity)),
+        }
+        self.crossed()
+    }
+    /// Replace the book with a snapshot, returning the deltas that take the old
+    /// book to the new one.
+    ///
+    /// Levels with zero or negative quantity are skipped.
+    /// Use [`Self::validate`] to check that the result isn't crossed.
+    pub fn apply_snapshot(
+        &mut self,
+        bids: impl IntoIterator<Item = (PriceT, QuantityT)>,
+        asks: impl IntoIterator<Item = (PriceT, QuantityT)>,
+    ) -> Vec<ExchangeMessage<PriceT, QuantityT>> {
+        let mut messages = vec![];
+        for (side, levels) in [
+            (Side::Bid, Either::Left(bids.into_iter())),
+            (Side::Ask, Either::Right(asks.into_iter())),
+        ] {
+            let message = |price, quantity| match side {
+                Side::Bid => ExchangeMessage::Buy { price, quantity },
+                Side::Ask => ExchangeMessage::Sell { price, quantity },
+            };
+            let new = self.side_mut(side);
+            let old = mem::take(new);
+            new.extend(levels.filter(|(_, it)| *it > QuantityT::zero()));
+            for price in old.keys().filter(|it| !new.contains_key(it)) {
+                messages.push(message(price.clone(), QuantityT::zero()))
+            }
+            for (price, quantity) in new.iter() {
+                if old.get(price) != Some(quantity) {
+                    messages.push(message(price.clone(), quantity.clone()))
+                }
+            }
+        }
+        messages
+    }
+    /// [`Self::apply`] each of `messages`, warning if they cross the book.
+    ///
+    /// Messages with a negative quantity are ignored by the book, so are dropped from `messages` too.
+    /// [`BookError::Needless`] is left to the consumer, e.g [`ArbitrageFinder`](crate::ArbitrageFinder) reports it.
+    pub fn apply_all(&mut self, messages: &mut Vec<ExchangeMessage<PriceT, QuantityT>>) {
+        messages.retain(|message| match self.apply(message.clone()) {
+            Ok(()) | Err(BookError::Needless { .. }) => true,
+            Err(BookError::Crossed { .. }) => {
+                warn!("update crossed the book");
+                true
+            }
+            Err(BookError::Negative { side, .. }) => {
+                warn!(?side, "dropping an update with a negative quantity");
+                false
+            }
+        })
+    }
+    /// Check that the book isn't crossed.
+    pub fn validate(&self) -> Result<(), BookError<PriceT>> {
+        self.crossed()
+    }
+    fn crossed(&self) -> Result<(), BookError<PriceT>> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some((bid, _)), Some((ask, _))) if bid >= ask => Err(BookError::Crossed {
+                bid: bid.clone(),
+                ask: ask.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<PriceT, QuantityT> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+}
+
+impl<PriceT, QuantityT> OrderBook<PriceT, QuantityT>
+where
+    PriceT: Ord,
+{
+    /// The highest bid.
+    pub fn best_bid(&self) -> Option<(&PriceT, &QuantityT)> {
+        self.bids.last_key_value()
+    }
+    /// The lowest ask.
+    pub fn best_ask(&self) -> Option<(&PriceT, &QuantityT)> {
+        self.asks.first_key_value()
+    }
+    /// Levels on `side`, best first.
+    pub fn levels(&self, side: Side) -> impl Iterator<Item = (&PriceT, &QuantityT)> {
+        match side {
+            Side::Bid => Either::Left(self.bids.iter().rev()),
+            Side::Ask => Either::Right(self.asks.iter()),
+        }
+    }
+    /// The number of price levels on `side`.
+    pub fn len(&self, side: Side) -> usize {
+        match side {
+            Side::Bid => self.bids.len(),
+            Side::Ask => self.asks.len(),
+        }
+    }
+    /// Whether there are no levels on either side.
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+    /// Total quantity on `side`.
+    pub fn depth(&self, side: Side) -> QuantityT
+    where
+        QuantityT: Zero + Clone,
+    {
+        self.levels(side)
+            .fold(QuantityT::zero(), |acc, (_, quantity)| {
+                acc + quantity.clone()
+            })
+    }
+}
+
+impl<PriceT, QuantityT> Default for OrderBook<PriceT, QuantityT> {
+    fn default() -> Self {
+        Self {
+            bids: Default::default(),
+            asks: Default::default(),
+        }
+    }
+}
+
+/// The original text of each level, keyed by price, for exchanges that checksum it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct TextBook<PriceT> {
+    /// Price then quantity.
+    pub bids: BTreeMap<PriceT, (String, String)>,
+    pub asks: BTreeMap<PriceT, (String, String)>,
+}
+
+impl<PriceT> Default for TextBook<PriceT> {
+    fn default() -> Self {
+        Self {
+            bids: Default::default(),
+            asks: Default::default(),
+        }
+    }
+}
+
+impl<PriceT> TextBook<PriceT>
+where
+    PriceT: Ord + Clone,
+{
+    /// Insert a level, or remove it if `quantity` is zero,
+    /// and push the corresponding [`ExchangeMessage`].
+    pub fn apply<QuantityT: Zero>(
+        &mut self,
+        side: Side,
+        (price, price_text): (PriceT, String),
+        (quantity, quantity_text): (QuantityT, String),
+        messages: &mut Vec<ExchangeMessage<PriceT, QuantityT>>,
+    ) {
+        let levels = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        match quantity.is_zero() {
+            true => drop(levels.remove(&price)),
+            false => drop(levels.insert(price.clone(), (price_text, quantity_text))),
+        }
+        messages.push(match side {
+            Side::Bid => ExchangeMessage::Buy { price, quantity },
+            Side::Ask => ExchangeMessage::Sell { price, quantity },
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::assert_equal;
+
+    use super::*;
+
+    fn buy(price: i32, quantity: i32) -> ExchangeMessage<i32, i32> {
+        ExchangeMessage::Buy { price, quantity }
+    }
+    fn sell(price: i32, quantity: i32) -> ExchangeMessage<i32, i32> {
+        ExchangeMessage::Sell { price, quantity }
+    }
+
+    #[test]
+    fn apply() {
+        let mut book = OrderBook::default();
+        book.apply(buy(9, 1)).unwrap();
+        book.apply(buy(10, 2)).unwrap();
+        book.apply(sell(11, 3)).unwrap();
+        book.apply(sell(12, 4)).unwrap();
+        assert_eq!(book.best_bid(), Some((&10, &2)));
+        assert_eq!(book.best_ask(), Some((&11, &3)));
+        assert_equal(book.levels(Side::Bid), [(&10, &2), (&9, &1)]);
+        assert_eq!(book.depth(Side::Ask), 7);
+
+        book.apply(sell(11, 0)).unwrap();
+        assert_eq!(book.len(Side::Ask), 1);
+        assert_eq!(
+            book.apply(sell(11, 0)),
+            Err(BookError::Needless {
+                side: Side::Ask,
+                price: 11
+            })
+        );
+        assert_eq!(
+            book.apply(buy(8, -1)),
+            Err(BookError::Negative {
+                side: Side::Bid,
+                price: 8
+            })
+        );
+        assert_eq!(book.len(Side::Bid), 2);
+        assert_eq!(
+            book.apply(buy(12, 1)),
+            Err(BookError::Crossed { bid: 12, ask: 12 })
+        );
+
+        book.apply(ExchangeMessage::Resynced).unwrap();
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn apply_all() {
+        let mut book = OrderBook::default();
+        let mut messages = vec![buy(10, 1), buy(9, -1), sell(9, 1), sell(11, 0)];
+        book.apply_all(&mut messages);
+        // crossed and needless messages are still passed on
+        assert_eq!(messages, [buy(10, 1), sell(9, 1), sell(11, 0)]);
+        assert_equal(book.levels(Side::Bid), [(&10, &1)]);
+        assert_equal(book.levels(Side::Ask), [(&9, &1)]);
+    }
+
+    #[test]
+    fn apply_snapshot() {
+        let mut book = OrderBook::default();
+        assert_eq!(
+            book.apply_snapshot([(10, 1), (9, 2)], [(11, 1)]),
+            [buy(9, 2), buy(10, 1), sell(11, 1)]
+        );
+        book.apply(sell(12, 3)).unwrap();
+        assert_eq!(
+            book.apply_snapshot([(10, 1), (9, 2)], [(11, 1), (12, 3)]),
+            []
+        );
+        assert_eq!(
+            book.apply_snapshot([(10, 5)], [(11, 1), (12, 3), (13, 0), (14, -1)]),
+            [buy(9, 0), buy(10, 5)]
+        );
+        book.validate().unwrap();
+        book.apply_snapshot([(11, 1)], [(11, 1)]);
+        assert_eq!(
+            book.validate(),
+            Err(BookError::Crossed { bid: 11, ask: 11 })
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Bybit's [orderbook topic](https://bybit-exchange.github.io/docs/v5/websocket/public/orderbook)
+//! for linear (USDT and USDC) contracts.
+//!
+//! Snapshots may arrive at any time, e.g after a service restart, or periodically
+//! for [`BybitDepth::One`].
+//! They replace the book, and we emit whatever deletes and updates that implies.
+
+use std::io;
+
+use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use io_extra::IoErrorExt as _;
+use num_traits::Zero;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use super::{
+    bail, orders2exchangemessages, recv_json, send_json, BybitDepth, ExchangeMessage, FeedError,
+    OrderBook, WsError, WsMessage, WsResult,
+};
+
+/// Input channel should NOT have had messages sent over it...
+/// `symbol` should be e.g `BTCUSDT`.
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    symbol: String,
+    depth: BybitDepth,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    stream::once(_protocol(s, symbol, depth)).flatten()
+}
+
+/// ```text
+///           ┌───┐          ┌────────┐
+///           │bot│          │exchange│
+///           └─┬─┘          └───┬────┘
+///             │   subscribe    │
+///             │───────────────>│
+///             │                │
+///             │   subscribed   │
+///             │<─ ─ ─ ─ ─ ─ ─ ─│
+///             │                │
+/// ╔═══════╤═══╪════════════════╪═════════════════╗
+/// ║ LOOP  │  infinite          │                 ║
+/// ╟───────┘   │    snapshot    │                 ║
+/// ║           │<───────────────│                 ║
+/// ║           │                │                 ║
+/// ║ ╔═══════╤═╪════════════════╪═══════════════╗ ║
+/// ║ ║ LOOP  │  finite/deltas   │               ║ ║
+/// ║ ╟───────┘ │     delta      │               ║ ║
+/// ║ ║         │<─ ─ ─ ─ ─ ─ ─ ─│               ║ ║
+/// ║ ╚═════════╪════════════════╪═══════════════╝ ║
+/// ╚═══════════╪════════════════╪═════════════════╝
+///           ┌─┴─┐          ┌───┴────┐
+///           │bot│          │exchange│
+///           └───┘          └────────┘
+/// ```
+///
+/// Each delta's `u` follows on from the previous message's,
+/// and a gap fails the stream with [`FeedError::Gap`].
+async fn _protocol<PriceT, QuantityT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    symbol: String,
+    depth: BybitDepth,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    let topic = format!("orderbook.{}.{symbol}", depth.levels());
+    if let Err(e) = send_json(&mut s, json!({"op": "subscribe", "args": [topic]})).await {
+        bail!(e)
+    }
+    let state = State {
+        topic,
+        book: OrderBook::default(),
+        update_id: None,
+    };
+    Either::Right(
+        stream::try_unfold((s, state), |(mut s, mut state)| async move {
+            let messages = match recv_json::<Push<PriceT, QuantityT>>(&mut s).await? {
+                Push {
+                    success: Some(false),
+                    ret_msg,
+                    ..
+                } => {
+                    return Err(WsError::Io(io::Error::invalid_data(format!(
+                        "request failed: {}",
+                        ret_msg.unwrap_or_default()
+                    ))))
+                }
+                Push {
+                    topic: Some(topic),
+                    kind: Some(kind),
+                    data: Some(data),
+                    ..
+                } if topic == state.topic => state.apply(kind, data)?,
+                _ => vec![],
+            };
+            Ok(Some((
+                stream::iter(messages.into_iter().map(Ok)),
+                (s, state),
+            )))
+        })
+        .try_flatten(),
+    )
+}
+
+struct State<PriceT, QuantityT> {
+    topic: String,
+    book: OrderBook<PriceT, QuantityT>,
+    /// Of the last message, or [`None`] before the first snapshot.
+    update_id: Option<u64>,
+}
+
+impl<PriceT, QuantityT> State<PriceT, QuantityT>
+where
+    PriceT: Ord + Clone,
+    QuantityT: Zero + PartialOrd + Clone,
+{
+    fn apply(
+        &mut self,
+        kind: Kind,
+        Data {
+            bids,
+            asks,
+            update_id,
+        }: Data<PriceT, QuantityT>,
+    ) -> WsResult<Vec<ExchangeMessage<PriceT, QuantityT>>> {
+        let messages = match (kind, self.update_id) {
+            (Kind::Snapshot, _) => self.book.apply_snapshot(bids, asks),
+            (Kind::Delta, None) => {
+                return Err(WsError::Io(io::Error::invalid_data(
+                    "received a delta before a snapshot",
+                )))
+            }
+            (Kind::Delta, Some(last)) if update_id != last + 1 => {
+                return Err(WsError::Io(io::Error::invalid_data(FeedError::Gap {
+                    expected: last + 1,
+                    got: update_id,
+                })))
+            }
+            (Kind::Delta, Some(_)) => {
+                let mut messages = orders2exchangemessages(bids, asks);
+                self.book.apply_all(&mut messages);
+                messages
+            }
+        };
+        self.update_id = Some(update_id);
+        Ok(messages)
+    }
+}
+
+/// Every message we might receive, which are distinguished by which fields are present.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = "PriceT: Serialize, QuantityT: Serialize"
+))]
+struct Push<PriceT, QuantityT> {
+    /// For responses to requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    success: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ret_msg: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    kind: Option<Kind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Data<PriceT, QuantityT>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Snapshot,
+    Delta,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+struct Data<PriceT, QuantityT> {
+    #[serde(rename = "b")]
+    bids: Vec<(PriceT, QuantityT)>,
+    #[serde(rename = "a")]
+    asks: Vec<(PriceT, QuantityT)>,
+    /// `1` on a snapshot after a service restart.
+    #[serde(rename = "u")]
+    update_id: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::integrations::{round_trip, u16f16};
+
+    #[test]
+    fn deser() {
+        round_trip(
+            Push {
+                success: None,
+                ret_msg: None,
+                topic: Some("orderbook.50.BTCUSDT".into()),
+                kind: Some(Kind::Snapshot),
+                data: Some(Data {
+                    bids: vec![(u16f16::lit("16493.5"), u16f16::lit("0.5"))],
+                    asks: vec![],
+                    update_id: 18521288,
+                }),
+            },
+            json!({
+                "topic": "orderbook.50.BTCUSDT",
+                "type": "snapshot",
+                "data": {"b": [["16493.5", "0.5"]], "a": [], "u": 18521288}
+            }),
+        );
+        round_trip(
+            Push::<u16f16, u16f16> {
+                success: Some(true),
+                ret_msg: Some("".into()),
+                topic: None,
+                kind: None,
+                data: None,
+            },
+            json!({"success": true, "ret_msg": ""}),
+        );
+    }
+
+    #[test]
+    fn sequence() {
+        let mut state = State {
+            topic: "orderbook.50.BTCUSDT".into(),
+            book: OrderBook::<u32, u32>::default(),
+            update_id: None,
+        };
+        let data = |bids, asks, update_id| Data {
+            bids,
+            asks,
+            update_id,
+        };
+        state
+            .apply(Kind::Snapshot, data(vec![(10, 1)], vec![(11, 1)], 100))
+            .unwrap();
+        assert_eq!(
+            state
+                .apply(Kind::Delta, data(vec![(9, 2)], vec![], 101))
+                .unwrap(),
+            [ExchangeMessage::Buy {
+                price: 9,
+                quantity: 2
+            }]
+        );
+        // the service restarted, and 9 is gone
+        assert_eq!(
+            state
+                .apply(Kind::Snapshot, data(vec![(10, 1)], vec![(12, 1)], 1))
+                .unwrap(),
+            [
+                ExchangeMessage::Buy {
+                    price: 9,
+                    quantity: 0
+                },
+                ExchangeMessage::Sell {
+                    price: 11,
+                    quantity: 0
+                },
+                ExchangeMessage::Sell {
+                    price: 12,
+                    quantity: 1
+                },
+            ]
+        );
+        assert!(state
+            .apply(Kind::Delta, data(vec![(9, 1)], vec![], 2))
+            .is_ok());
+        let gap = state
+            .apply(Kind::Delta, data(vec![], vec![], 4))
+            .unwrap_err();
+        assert_eq!(
+            FeedError::of(&gap),
+            Some(&FeedError::Gap {
+                expected: 3,
+                got: 4
+            })
+        );
+    }
+}
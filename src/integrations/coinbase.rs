@@ -0,0 +1,345 @@
+//! Coinbase Advanced Trade's [level2 channel](https://docs.cdp.coinbase.com/advanced-trade/docs/ws-channels#level2-channel).
+//!
+//! Every message on a connection carries a `sequence_num`, regardless of channel.
+
+use std::io;
+
+use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use io_extra::IoErrorExt as _;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use super::{bail, recv_json, send_json, ExchangeMessage, FeedError, WsError, WsMessage, WsResult};
+
+/// Input channel should NOT have had messages sent over it...
+/// `product_id` should be e.g `BTC-USD`.
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    product_id: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    stream::once(_protocol(s, product_id)).flatten()
+}
+
+/// ```text
+///           ┌───┐          ┌────────┐
+///           │bot│          │exchange│
+///           └─┬─┘          └───┬────┘
+///             │   subscribe    │
+///             │───────────────>│
+///             │                │
+///             │ subscriptions  │
+///             │<─ ─ ─ ─ ─ ─ ─ ─│
+///             │                │
+///             │    snapshot    │
+///             │<───────────────│
+///             │                │
+/// ╔═══════╤═══╪════════════════╪═══╗
+/// ║ LOOP  │  infinite          │   ║
+/// ╟───────┘   │     update     │   ║
+/// ║           │<─ ─ ─ ─ ─ ─ ─ ─│   ║
+/// ╚═══════════╪════════════════╪═══╝
+///           ┌─┴─┐          ┌───┴────┐
+///           │bot│          │exchange│
+///           └───┘          └────────┘
+/// ```
+///
+/// The `subscriptions` message may arrive before or after the snapshot.
+/// A gap in `sequence_num` fails the stream with [`FeedError::Gap`].
+async fn _protocol<PriceT, QuantityT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    product_id: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned,
+{
+    if let Err(e) = send_json(
+        &mut s,
+        json!({"type": "subscribe", "channel": "level2", "product_ids": [product_id]}),
+    )
+    .await
+    {
+        bail!(e)
+    };
+    let state = State {
+        product_id,
+        sequence_num: None,
+        snapshotted: false,
+    };
+    Either::Right(
+        stream::try_unfold((s, state), |(mut s, mut state)| async move {
+            let messages = state.apply(recv_json(&mut s).await?)?;
+            Ok::<_, WsError>(Some((
+                stream::iter(messages.into_iter().map(Ok)),
+                (s, state),
+            )))
+        })
+        .try_flatten(),
+    )
+}
+
+struct State {
+    product_id: String,
+    /// Of the last message.
+    sequence_num: Option<u64>,
+    snapshotted: bool,
+}
+
+impl State {
+    fn apply<PriceT, QuantityT>(
+        &mut self,
+        Envelope {
+            sequence_num,
+            message,
+        }: Envelope<PriceT, QuantityT>,
+    ) -> WsResult<Vec<ExchangeMessage<PriceT, QuantityT>>> {
+        if let Some(last) = self.sequence_num {
+            if sequence_num != last + 1 {
+                return Err(WsError::Io(io::Error::invalid_data(FeedError::Gap {
+                    expected: last + 1,
+                    got: sequence_num,
+                })));
+            }
+        }
+        self.sequence_num = Some(sequence_num);
+        let mut messages = vec![];
+        if let Message::L2Data { events } = message {
+            for event in events {
+                let (product_id, updates) = match event {
+                    Event::Snapshot {
+                        product_id,
+                        updates,
+                    } => {
+                        if product_id == self.product_id {
+                            if self.snapshotted {
+                                messages.push(ExchangeMessage::Resynced)
+                            }
+                            self.snapshotted = true;
+                        }
+                        (product_id, updates)
+                    }
+                    Event::Update {
+                        product_id,
+                        updates,
+                    } => (product_id, updates),
+                };
+                if product_id != self.product_id {
+                    continue;
+                }
+                messages.extend(updates.into_iter().map(
+                    |Update {
+                         side,
+                         price_level,
+                         new_quantity,
+                     }| match side {
+                        Side::Bid => ExchangeMessage::Buy {
+                            price: price_level,
+                            quantity: new_quantity,
+                        },
+                        Side::Offer => ExchangeMessage::Sell {
+                            price: price_level,
+                            quantity: new_quantity,
+                        },
+                    },
+                ))
+            }
+        }
+        Ok(messages)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = "PriceT: Serialize, QuantityT: Serialize"
+))]
+struct Envelope<PriceT, QuantityT> {
+    sequence_num: u64,
+    #[serde(flatten)]
+    message: Message<PriceT, QuantityT>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(tag = "channel", rename_all = "snake_case")]
+enum Message<PriceT, QuantityT> {
+    L2Data {
+        events: Vec<Event<PriceT, QuantityT>>,
+    },
+    /// `subscriptions`, `heartbeats` etc.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Event<PriceT, QuantityT> {
+    Snapshot {
+        product_id: String,
+        updates: Vec<Update<PriceT, QuantityT>>,
+    },
+    Update {
+        product_id: String,
+        updates: Vec<Update<PriceT, QuantityT>>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+struct Update<PriceT, QuantityT> {
+    side: Side,
+    price_level: PriceT,
+    /// Zero if the level has been removed.
+    new_quantity: QuantityT,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Side {
+    Bid,
+    Offer,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::integrations::{round_trip, u16f16};
+
+    #[test]
+    fn deser() {
+        round_trip(
+            Envelope {
+                sequence_num: 0,
+                message: Message::L2Data {
+                    events: vec![Event::Snapshot {
+                        product_id: "BTC-USD".into(),
+                        updates: vec![
+                            Update {
+                                side: Side::Bid,
+                                price_level: u16f16::lit("123"),
+                                new_quantity: u16f16::lit("456"),
+                            },
+                            Update {
+                                side: Side::Offer,
+                                price_level: u16f16::lit("789"),
+                                new_quantity: u16f16::lit("0"),
+                            },
+                        ],
+                    }],
+                },
+            },
+            json!({
+                "channel": "l2_data",
+                "sequence_num": 0,
+                "events": [{
+                    "type": "snapshot",
+                    "product_id": "BTC-USD",
+                    "updates": [
+                        {"side": "bid", "price_level": "123", "new_quantity": "456"},
+                        {"side": "offer", "price_level": "789", "new_quantity": "0"},
+                    ]
+                }]
+            }),
+        );
+        round_trip(
+            Envelope::<u16f16, u16f16> {
+                sequence_num: 1,
+                message: Message::Other,
+            },
+            json!({"channel": "other", "sequence_num": 1}),
+        );
+    }
+
+    #[test]
+    fn ignores_other_channels() {
+        let it = serde_json::from_value::<Envelope<u16f16, u16f16>>(json!({
+            "channel": "subscriptions",
+            "client_id": "",
+            "timestamp": "2023-02-09T20:32:50.714964855Z",
+            "sequence_num": 1,
+            "events": [{"subscriptions": {"level2": ["BTC-USD"]}}]
+        }))
+        .unwrap();
+        assert_eq!(it.message, Message::Other);
+    }
+
+    #[test]
+    fn sequence() {
+        let mut state = State {
+            product_id: "BTC-USD".into(),
+            sequence_num: None,
+            snapshotted: false,
+        };
+        let event = |snapshot, product_id: &str| {
+            let updates = vec![Update {
+                side: Side::Bid,
+                price_level: 10,
+                new_quantity: 1,
+            }];
+            let product_id = product_id.into();
+            match snapshot {
+                true => Event::Snapshot {
+                    product_id,
+                    updates,
+                },
+                false => Event::Update {
+                    product_id,
+                    updates,
+                },
+            }
+        };
+        let envelope = |sequence_num, events| Envelope {
+            sequence_num,
+            message: Message::L2Data { events },
+        };
+        let buy = ExchangeMessage::Buy {
+            price: 10,
+            quantity: 1,
+        };
+
+        // the first message's number is arbitrary
+        assert_eq!(
+            state
+                .apply(Envelope::<u32, u32> {
+                    sequence_num: 3,
+                    message: Message::Other,
+                })
+                .unwrap(),
+            []
+        );
+        assert_eq!(
+            state
+                .apply(envelope(4, vec![event(true, "BTC-USD")]))
+                .unwrap(),
+            [buy]
+        );
+        assert_eq!(
+            state
+                .apply(envelope(
+                    5,
+                    vec![event(false, "ETH-USD"), event(false, "BTC-USD")]
+                ))
+                .unwrap(),
+            [buy]
+        );
+        assert_eq!(
+            state
+                .apply(envelope(6, vec![event(true, "BTC-USD")]))
+                .unwrap(),
+            [ExchangeMessage::Resynced, buy]
+        );
+
+        let gap = state.apply(envelope(8, vec![event(false, "BTC-USD")]));
+        assert_eq!(
+            FeedError::of(&gap.unwrap_err()),
+            Some(&FeedError::Gap {
+                expected: 7,
+                got: 8
+            })
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Deribit's [book channel](https://docs.deribit.com/#book-instrument_name-interval),
+//! over JSON-RPC.
+//!
+//! Prices and amounts are sent as JSON numbers, see [`JsonNumber`].
+
+use std::io;
+
+use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use io_extra::IoErrorExt as _;
+use num_traits::Zero;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use crate::Side;
+
+use super::{
+    bail, deserialize_json, recv_raw, send_json, ExchangeMessage, FeedError, JsonNumber, WsError,
+    WsMessage, WsResult,
+};
+
+/// The id of our only request.
+const SUBSCRIBE: u64 = 1;
+
+/// Input channel should NOT have had messages sent over it...
+/// `instrument_name` should be e.g `BTC-PERPETUAL`.
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    instrument_name: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned + Zero,
+{
+    stream::once(_protocol(s, instrument_name)).flatten()
+}
+
+/// ```text
+///           ┌───┐          ┌────────┐
+///           │bot│          │exchange│
+///           └─┬─┘          └───┬────┘
+///             │   subscribe    │
+///             │───────────────>│
+///             │                │
+///             │    response    │
+///             │<─ ─ ─ ─ ─ ─ ─ ─│
+///             │                │
+///             │    snapshot    │
+///             │<───────────────│
+///             │                │
+/// ╔═══════╤═══╪════════════════╪═══╗
+/// ║ LOOP  │  infinite          │   ║
+/// ╟───────┘   │     change     │   ║
+/// ║           │<─ ─ ─ ─ ─ ─ ─ ─│   ║
+/// ╚═══════════╪════════════════╪═══╝
+///           ┌─┴─┐          ┌───┴────┐
+///           │bot│          │exchange│
+///           └───┘          └────────┘
+/// ```
+///
+/// Each change's `prev_change_id` is the `change_id` of the message before it,
+/// and a gap fails the stream with [`FeedError::Gap`].
+async fn _protocol<PriceT, QuantityT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    instrument_name: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned,
+    QuantityT: DeserializeOwned + Zero,
+{
+    let channel = format!("book.{instrument_name}.raw");
+    if let Err(e) = send_json(
+        &mut s,
+        Request {
+            jsonrpc: Version,
+            id: SUBSCRIBE,
+            method: "public/subscribe".into(),
+            params: json!({"channels": [channel]}),
+        },
+    )
+    .await
+    {
+        bail!(e)
+    }
+    Either::Right(
+        stream::try_unfold(
+            (s, channel, None),
+            |(mut s, channel, mut change_id): (_, _, Option<u64>)| async move {
+                let raw = recv_raw(&mut s).await?;
+                let messages = match deserialize_json::<Header>(&raw)? {
+                    Header {
+                        id: Some(SUBSCRIBE),
+                        error: Some(RpcError { code, message }),
+                        ..
+                    } => {
+                        return Err(WsError::Io(io::Error::invalid_data(format!(
+                            "request failed: {message} ({code})"
+                        ))))
+                    }
+                    Header {
+                        id: Some(SUBSCRIBE),
+                        result: Some(subscribed),
+                        ..
+                    } if !subscribed.contains(&channel) => {
+                        return Err(WsError::Io(io::Error::invalid_data(format!(
+                            "not subscribed to {channel}"
+                        ))))
+                    }
+                    Header {
+                        method: Some(method),
+                        ..
+                    } if method == "subscription" => {
+                        let Notification {
+                            params: Params { channel: it, data },
+                        } = deserialize_json::<Notification<PriceT, QuantityT>>(&raw)?;
+                        if it == channel {
+                            apply(&mut change_id, data)?
+                        } else {
+                            vec![]
+                        }
+                    }
+                    _ => vec![],
+                };
+                Ok(Some((
+                    stream::iter(messages.into_iter().map(Ok)),
+                    (s, channel, change_id),
+                )))
+            },
+        )
+        .try_flatten(),
+    )
+}
+
+/// Check `data` follows on from `change_id`, which is then updated.
+fn apply<PriceT, QuantityT: Zero>(
+    change_id: &mut Option<u64>,
+    BookData {
+        kind,
+        change_id: id,
+        prev_change_id,
+        bids,
+        asks,
+    }: BookData<PriceT, QuantityT>,
+) -> WsResult<Vec<ExchangeMessage<PriceT, QuantityT>>> {
+    let mut messages = vec![];
+    match (kind, *change_id, prev_change_id) {
+        (Kind::Snapshot, None, _) => {}
+        (Kind::Snapshot, Some(_), _) => messages.push(ExchangeMessage::Resynced),
+        (Kind::Change, Some(last), Some(prev)) if last == prev => {}
+        (Kind::Change, Some(last), Some(prev)) => {
+            return Err(WsError::Io(io::Error::invalid_data(FeedError::Gap {
+                expected: last,
+                got: prev,
+            })))
+        }
+        (Kind::Change, None, _) => {
+            return Err(WsError::Io(io::Error::invalid_data(
+                "received a change before a snapshot",
+            )))
+        }
+        (Kind::Change, Some(_), None) => {
+            return Err(WsError::Io(io::Error::invalid_data(
+                "change has no prev_change_id",
+            )))
+        }
+    }
+    *change_id = Some(id);
+    for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+        messages.extend(levels.into_iter().map(|Level(action, price, amount)| {
+            let (price, quantity) = match action {
+                Action::New | Action::Change => (price.value, amount.value),
+                Action::Delete => (price.value, QuantityT::zero()),
+            };
+            match side {
+                Side::Bid => ExchangeMessage::Buy { price, quantity },
+                Side::Ask => ExchangeMessage::Sell { price, quantity },
+            }
+        }))
+    }
+    Ok(messages)
+}
+
+/// Serializes as `"2.0"`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+struct Version;
+
+impl Serialize for Version {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("2.0")
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+struct Request<T> {
+    jsonrpc: Version,
+    id: u64,
+    method: String,
+    params: T,
+}
+
+/// Just enough to tell responses and notifications apart.
+#[derive(Deserialize, Debug, PartialEq, Eq, Hash, Clone, Default)]
+struct Header {
+    /// For responses.
+    id: Option<u64>,
+    result: Option<Vec<String>>,
+    error: Option<RpcError>,
+    /// For notifications.
+    method: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct Notification<PriceT, QuantityT> {
+    params: Params<PriceT, QuantityT>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct Params<PriceT, QuantityT> {
+    channel: String,
+    data: BookData<PriceT, QuantityT>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct BookData<PriceT, QuantityT> {
+    #[serde(rename = "type")]
+    kind: Kind,
+    change_id: u64,
+    /// Absent on snapshots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prev_change_id: Option<u64>,
+    bids: Vec<Level<PriceT, QuantityT>>,
+    asks: Vec<Level<PriceT, QuantityT>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Snapshot,
+    Change,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct Level<PriceT, QuantityT>(Action, JsonNumber<PriceT>, JsonNumber<QuantityT>);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    New,
+    Change,
+    Delete,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::integrations::u16f16;
+
+    fn level(action: Action, price: &str, amount: &str) -> Level<u16f16, u16f16> {
+        Level(
+            action,
+            JsonNumber {
+                text: price.into(),
+                value: u16f16::from_str(price).unwrap(),
+                quoted: false,
+            },
+            JsonNumber {
+                text: amount.into(),
+                value: u16f16::from_str(amount).unwrap(),
+                quoted: false,
+            },
+        )
+    }
+
+    fn book_data(
+        kind: Kind,
+        change_id: u64,
+        prev_change_id: Option<u64>,
+    ) -> BookData<u16f16, u16f16> {
+        BookData {
+            kind,
+            change_id,
+            prev_change_id,
+            bids: vec![],
+            asks: vec![],
+        }
+    }
+
+    #[test]
+    fn deser() {
+        let json = json!({
+            "jsonrpc": "2.0",
+            "method": "subscription",
+            "params": {
+                "channel": "book.BTC-PERPETUAL.raw",
+                "data": {
+                    "type": "change",
+                    "change_id": 2,
+                    "prev_change_id": 1,
+                    "bids": [["new", 5042.5, 10.0]],
+                    "asks": [["delete", 5043, 0]]
+                }
+            }
+        });
+        let text = json.to_string();
+        assert_eq!(
+            deserialize_json::<Header>(&Either::Right(text.clone())).unwrap(),
+            Header {
+                method: Some("subscription".into()),
+                ..Default::default()
+            }
+        );
+        let it = deserialize_json::<Notification<u16f16, u16f16>>(&Either::Right(text)).unwrap();
+        assert_eq!(
+            it,
+            Notification {
+                params: Params {
+                    channel: "book.BTC-PERPETUAL.raw".into(),
+                    data: BookData {
+                        bids: vec![level(Action::New, "5042.5", "10.0")],
+                        asks: vec![level(Action::Delete, "5043", "0")],
+                        ..book_data(Kind::Change, 2, Some(1))
+                    }
+                }
+            }
+        );
+        assert_eq!(serde_json::to_value(&it).unwrap()["params"], json["params"]);
+        assert_eq!(
+            serde_json::to_value(Request {
+                jsonrpc: Version,
+                id: SUBSCRIBE,
+                method: "public/subscribe".into(),
+                params: json!({"channels": ["book.BTC-PERPETUAL.raw"]}),
+            })
+            .unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "public/subscribe",
+                "params": {"channels": ["book.BTC-PERPETUAL.raw"]}
+            })
+        );
+    }
+
+    #[test]
+    fn continuity() {
+        let mut change_id = None;
+        assert!(apply(&mut change_id, book_data(Kind::Change, 2, Some(1))).is_err());
+        assert_eq!(
+            apply(
+                &mut change_id,
+                BookData {
+                    bids: vec![level(Action::New, "1", "2")],
+                    ..book_data(Kind::Snapshot, 1, None)
+                }
+            )
+            .unwrap(),
+            [ExchangeMessage::Buy {
+                price: u16f16::lit("1"),
+                quantity: u16f16::lit("2")
+            }]
+        );
+        assert_eq!(
+            apply(
+                &mut change_id,
+                BookData {
+                    bids: vec![level(Action::Delete, "1", "2")],
+                    ..book_data(Kind::Change, 2, Some(1))
+                }
+            )
+            .unwrap(),
+            [ExchangeMessage::Buy {
+                price: u16f16::lit("1"),
+                quantity: u16f16::ZERO
+            }]
+        );
+        let gap = apply(&mut change_id, book_data(Kind::Change, 5, Some(4))).unwrap_err();
+        assert_eq!(
+            FeedError::of(&gap),
+            Some(&FeedError::Gap {
+                expected: 2,
+                got: 4
+            })
+        );
+        assert_eq!(
+            apply(&mut change_id, book_data(Kind::Snapshot, 9, None)).unwrap(),
+            [ExchangeMessage::Resynced]
+        );
+    }
+}
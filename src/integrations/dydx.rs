@@ -4,30 +4,18 @@
 
 use std::io;
 
-use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use futures::{future::Either, Sink, Stream};
 use io_extra::IoErrorExt as _;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
 
-use super::{bail, recv_json, send_json, ExchangeMessage, WsError, WsMessage, WsResult};
-
-/// Input channel should NOT have had messages sent over it...
-/// `id` should be e.g `BTC-USD`.
-pub fn protocol<PriceT, QuantityT>(
-    // TODO(aatifsyed): could relax `Unpin` bound if we were willing to `Box::pin`
-    //                  in `stream::try_unfold`.
-    //                  This would also allow us to return an `Unpin` stream...
-    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
-    id: impl Into<String>,
-) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
-where
-    PriceT: DeserializeOwned,
-    QuantityT: DeserializeOwned,
-{
-    // return a bare stream rather than a future resolving to a stream...
-    stream::once(_protocol(s, id.into())).flatten()
-}
+use super::{
+    deserialize_json, orders2exchangemessages, recv_json, send_json, Exchange, ExchangeMessage,
+    FeedError, WsError, WsMessage, WsResult,
+};
 
+/// [dYdX v4](https://docs.dydx.exchange/developers/indexer/indexer_websocket#orderbooks).
+///
 /// ```text
 ///           ┌────────┐          ┌───┐          
 ///           │exchange│          │bot│          
@@ -53,74 +41,172 @@ where
 ///           └────────┘          └───┘          
 /// ```
 /// <https://www.plantuml.com/plantuml/uml/POv12WD120Jlli8Fv0Dx2FiLnp5POQF3wa2U7tCOSeW7eRkheVT8kdA-Jf0t7sHFmTiTc-U6x6R2AHrAVjr5R1Yp1L_QvByLHYCEJpZT1wezr3G5iEx7BdYEJXMATTZhrOmF>
-// TODO(aatifsyed): could check other invariants on borrowed messages...:
-// - channel id doesn't change
-// - sequence number is monotonic...
-async fn _protocol<PriceT, QuantityT>(
-    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
-    id: String,
-) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+///
+/// Every message carries a `message_id`, which counts up from `0` on each connection.
+/// We only subscribe to one channel, so any jump is a missed message,
+/// and fails the stream with [`FeedError::Gap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Dydx;
+
+impl<PriceT, QuantityT> Exchange<PriceT, QuantityT> for Dydx
 where
     PriceT: DeserializeOwned,
     QuantityT: DeserializeOwned,
 {
-    match recv_json::<Message<(), ()>>(&mut s).await {
-        Ok(Message::Connected) => {}
-        Ok(_) => bail!(io::Error::invalid_data(
-            r#"expected to receive "connected""#
-        )),
-        Err(e) => bail!(e),
+    type State = DydxState;
+    fn mainnet(&self) -> String {
+        "wss://indexer.dydx.trade/v4/ws".into()
+    }
+    fn testnet(&self) -> Option<String> {
+        Some("wss://indexer.v4testnet.dydx.exchange/v4/ws".into())
+    }
+    fn symbol(&self, base: &str, quote: &str) -> String {
+        format!("{base}-{quote}")
     }
-    if let Err(e) = send_json(
-        &mut s,
-        json!({"type": "subscribe", "channel": "v4_orderbook", "id": id}),
-    )
-    .await
+    async fn handshake<S>(&self, s: &mut S, symbol: &str) -> WsResult<DydxState>
+    where
+        S: Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
     {
-        bail!(e)
+        let sequence = match recv_json::<Envelope<(), ()>>(&mut *s).await? {
+            Envelope {
+                message_id,
+                message: Message::Connected,
+                ..
+            } => Sequence::new(message_id, symbol.into()),
+            _ => {
+                return Err(WsError::Io(io::Error::invalid_data(
+                    r#"expected to receive "connected""#,
+                )))
+            }
+        };
+        send_json(
+            s,
+            json!({"type": "subscribe", "channel": "v4_orderbook", "id": symbol}),
+        )
+        .await?;
+        Ok(DydxState {
+            sequence,
+            subscribed: false,
+        })
     }
-    match recv_json(&mut s).await {
-        Ok(Message::Subscribed(Subscribed { bids, asks })) => {
-            let bids = bids
-                .into_iter()
-                .map(|Named { price, size }| ExchangeMessage::Buy {
-                    price,
-                    quantity: size,
-                });
-            let asks = asks
-                .into_iter()
-                .map(|Named { price, size }| ExchangeMessage::Sell {
-                    price,
-                    quantity: size,
-                });
-
-            let cont = stream::try_unfold(s, |mut it| async move {
-                match recv_json(&mut it).await {
-                    Ok(Message::Connected | Message::Subscribed(_)) => Err(WsError::Io(
-                        io::Error::invalid_data(r#"expected "channel_data""#),
-                    )),
-                    Ok(Message::ChannelData(ChannelData { bids, asks })) => {
-                        let bids = bids
-                            .into_iter()
-                            .map(|(price, quantity)| ExchangeMessage::Buy { price, quantity });
-                        let asks = asks
-                            .into_iter()
-                            .map(|(price, quantity)| ExchangeMessage::Sell { price, quantity });
-                        Ok(Some((stream::iter(bids.chain(asks).map(Ok)), it)))
-                    }
-                    Err(e) => Err(e),
-                }
-            })
-            .try_flatten();
-            Either::Right(stream::iter(bids.chain(asks).map(Ok)).chain(cont))
+    fn decode(
+        &self,
+        state: &mut DydxState,
+        raw: &Either<Vec<u8>, String>,
+    ) -> WsResult<Vec<ExchangeMessage<PriceT, QuantityT>>> {
+        let envelope = deserialize_json::<Envelope<PriceT, QuantityT>>(raw)?;
+        state.sequence.check(&envelope)?;
+        let (bids, asks) = match (envelope.message, state.subscribed) {
+            (Message::Subscribed(Subscribed { bids, asks }), false) => {
+                state.subscribed = true;
+                (
+                    bids.into_iter()
+                        .map(|Named { price, size }| (price, size))
+                        .collect(),
+                    asks.into_iter()
+                        .map(|Named { price, size }| (price, size))
+                        .collect(),
+                )
+            }
+            (Message::ChannelData(ChannelData { bids, asks }), true) => (bids, asks),
+            (_, false) => {
+                return Err(WsError::Io(io::Error::invalid_data(
+                    r#"expected to receive "subscribed""#,
+                )))
+            }
+            (_, true) => {
+                return Err(WsError::Io(io::Error::invalid_data(
+                    r#"expected "channel_data""#,
+                )))
+            }
+        };
+        Ok(orders2exchangemessages(bids, asks))
+    }
+}
+
+/// What [`Dydx`] carries between messages, see [`Exchange::State`].
+#[derive(Debug)]
+pub struct DydxState {
+    sequence: Sequence,
+    /// Whether we've had the snapshot.
+    subscribed: bool,
+}
+
+/// Invariants across the messages of a single subscription.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Sequence {
+    /// Of the last message.
+    message_id: u64,
+    /// The channel id we subscribed to.
+    id: String,
+    /// Of the first message that had one.
+    version: Option<String>,
+}
+
+impl Sequence {
+    fn new(message_id: u64, id: String) -> Self {
+        Self {
+            message_id,
+            id,
+            version: None,
+        }
+    }
+    fn check<PriceT, QuantityT>(
+        &mut self,
+        Envelope {
+            message_id,
+            id,
+            version,
+            message: _,
+        }: &Envelope<PriceT, QuantityT>,
+    ) -> WsResult<()> {
+        let expected = self.message_id + 1;
+        if *message_id != expected {
+            return Err(WsError::Io(io::Error::invalid_data(FeedError::Gap {
+                expected,
+                got: *message_id,
+            })));
         }
-        Ok(_) => bail!(io::Error::invalid_data(
-            r#"expected to receive "subscribed""#
-        )),
-        Err(e) => bail!(e),
+        self.message_id = *message_id;
+        if let Some(id) = id {
+            if *id != self.id {
+                return Err(WsError::Io(io::Error::invalid_data(format!(
+                    "expected channel id {}, got {}",
+                    self.id, id
+                ))));
+            }
+        }
+        match (&self.version, version) {
+            (Some(ours), Some(theirs)) if ours != theirs => {
+                return Err(WsError::Io(io::Error::invalid_data(format!(
+                    "channel version changed from {} to {}",
+                    ours, theirs
+                ))))
+            }
+            (None, Some(theirs)) => self.version = Some(theirs.clone()),
+            _ => {}
+        }
+        Ok(())
     }
 }
 
+/// The fields common to every [`Message`].
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = "PriceT: Serialize, QuantityT: Serialize"
+))]
+struct Envelope<PriceT, QuantityT> {
+    message_id: u64,
+    /// The channel id, absent on [`Message::Connected`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(flatten)]
+    message: Message<PriceT, QuantityT>,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 #[serde(tag = "type", content = "contents", rename_all = "snake_case")]
 enum Message<PriceT, QuantityT> {
@@ -201,4 +287,109 @@ mod tests {
             json!({"type": "subscribed", "contents": {"bids": [{"price": "123", "size": "456"}, {"price": "789", "size": "123"}]}}),
         )
     }
+    #[test]
+    fn envelope() {
+        round_trip(
+            Envelope::<u16f16, u16f16> {
+                message_id: 0,
+                id: None,
+                version: None,
+                message: Message::Connected,
+            },
+            json!({"type": "connected", "message_id": 0}),
+        );
+        round_trip(
+            Envelope {
+                message_id: 2,
+                id: Some(String::from("BTC-USD")),
+                version: Some(String::from("1.0.0")),
+                message: Message::ChannelData(ChannelData {
+                    bids: vec![],
+                    asks: vec![(u16f16::lit("123"), u16f16::lit("0"))],
+                }),
+            },
+            json!({
+                "type": "channel_data",
+                "message_id": 2,
+                "id": "BTC-USD",
+                "version": "1.0.0",
+                "contents": {"asks": [["123", "0"]]}
+            }),
+        );
+    }
+
+    #[test]
+    fn sequence() {
+        let envelope = |message_id, id: &str, version: &str| Envelope::<(), ()> {
+            message_id,
+            id: Some(id.into()),
+            version: Some(version.into()),
+            message: Message::Connected,
+        };
+        let mut sequence = Sequence::new(0, String::from("BTC-USD"));
+        sequence.check(&envelope(1, "BTC-USD", "1.0.0")).unwrap();
+        sequence.check(&envelope(2, "BTC-USD", "1.0.0")).unwrap();
+
+        let gap = sequence.clone().check(&envelope(4, "BTC-USD", "1.0.0"));
+        assert_eq!(
+            FeedError::of(&gap.unwrap_err()),
+            Some(&FeedError::Gap {
+                expected: 3,
+                got: 4
+            })
+        );
+        assert!(sequence
+            .clone()
+            .check(&envelope(2, "BTC-USD", "1.0.0"))
+            .is_err());
+        assert!(sequence
+            .clone()
+            .check(&envelope(3, "ETH-USD", "1.0.0"))
+            .is_err());
+        assert!(sequence
+            .clone()
+            .check(&envelope(3, "BTC-USD", "2.0.0"))
+            .is_err());
+    }
+
+    #[test]
+    fn decode() {
+        let mut state = DydxState {
+            sequence: Sequence::new(0, String::from("BTC-USD")),
+            subscribed: false,
+        };
+        let mut decode = |it: serde_json::Value| {
+            Exchange::<u16f16, u16f16>::decode(&Dydx, &mut state, &Either::Right(it.to_string()))
+        };
+        assert_eq!(
+            decode(json!({
+                "type": "subscribed",
+                "message_id": 1,
+                "contents": {"bids": [{"price": "123", "size": "456"}]}
+            }))
+            .unwrap(),
+            [ExchangeMessage::Buy {
+                price: u16f16::lit("123"),
+                quantity: u16f16::lit("456")
+            }]
+        );
+        assert_eq!(
+            decode(json!({
+                "type": "channel_data",
+                "message_id": 2,
+                "contents": {"asks": [["789", "0"]]}
+            }))
+            .unwrap(),
+            [ExchangeMessage::Sell {
+                price: u16f16::lit("789"),
+                quantity: u16f16::lit("0")
+            }]
+        );
+        assert!(decode(json!({
+            "type": "subscribed",
+            "message_id": 3,
+            "contents": {}
+        }))
+        .is_err());
+    }
 }
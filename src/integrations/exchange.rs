@@ -0,0 +1,86 @@
+//! A venue described by its parts, so that it can be driven by [`subscribe`](super::subscribe).
+
+use std::future::Future;
+
+use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+
+use super::{bail, recv_raw, ExchangeMessage, WsError, WsMessage, WsResult};
+
+/// A venue whose book can be streamed with [`subscribe`](super::subscribe).
+///
+/// This covers venues which subscribe once, and then decode each message on its own.
+/// Implement it to add venues outside this crate.
+///
+/// [`Self::decode`] only sees the message, not the socket, so a venue can't e.g:
+/// - resubscribe when its book goes out of sync, as [`kraken`](super::kraken()) and
+///   [`okx`](super::okx()) do on a checksum mismatch.
+/// - seed its book from a REST snapshot, as [`binance`](super::binance()) does.
+///
+/// Return an error from [`Self::decode`] instead.
+/// That connection's stream ends, and [`reconnecting`](super::reconnecting) starts a fresh one with
+/// [`ExchangeMessage::Resynced`].
+/// This crate's other venues implement their protocols directly, and aren't available as [`Exchange`]s.
+pub trait Exchange<PriceT, QuantityT> {
+    /// Carried between the messages of a single connection, e.g sequence numbers.
+    type State;
+    /// The websocket to connect to by default.
+    fn mainnet(&self) -> String;
+    /// The websocket to connect to for [`Endpoint::Testnet`](super::Endpoint::Testnet), if there is one.
+    fn testnet(&self) -> Option<String> {
+        None
+    }
+    /// This venue's name for the market of `base` in `quote`, e.g `BTC-USD`.
+    fn symbol(&self, base: &str, quote: &str) -> String;
+    /// Subscribe to `symbol` on a freshly connected socket.
+    fn handshake<S>(
+        &self,
+        s: &mut S,
+        symbol: &str,
+    ) -> impl Future<Output = tungstenite::Result<Self::State>>
+    where
+        S: Stream<Item = tungstenite::Result<tungstenite::Message>>
+            + Sink<tungstenite::Message, Error = tungstenite::Error>
+            + Unpin;
+    /// Decode the body of a text or binary message.
+    fn decode(
+        &self,
+        state: &mut Self::State,
+        raw: &Either<Vec<u8>, String>,
+    ) -> tungstenite::Result<Vec<ExchangeMessage<PriceT, QuantityT>>>;
+}
+
+/// Input channel should NOT have had messages sent over it...
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    exchange: impl Exchange<PriceT, QuantityT>,
+    symbol: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>> {
+    stream::once(_protocol(s, exchange, symbol)).flatten()
+}
+
+async fn _protocol<PriceT, QuantityT, ExchangeT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    exchange: ExchangeT,
+    symbol: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    ExchangeT: Exchange<PriceT, QuantityT>,
+{
+    let state = match exchange.handshake(&mut s, &symbol).await {
+        Ok(it) => it,
+        Err(e) => bail!(e),
+    };
+    Either::Right(
+        stream::try_unfold(
+            (s, exchange, state),
+            |(mut s, exchange, mut state)| async move {
+                let messages = exchange.decode(&mut state, &recv_raw(&mut s).await?)?;
+                Ok::<_, WsError>(Some((
+                    stream::iter(messages.into_iter().map(Ok)),
+                    (s, exchange, state),
+                )))
+            },
+        )
+        .try_flatten(),
+    )
+}
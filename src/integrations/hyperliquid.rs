@@ -0,0 +1,231 @@
+//! Hyperliquid's [l2Book subscription](https://hyperliquid.gitbook.io/hyperliquid-docs/for-developers/api/websocket/subscriptions)
+//! sends the top of the book in full with every message,
+//! so we diff each one against the last with [`OrderBook::apply_snapshot`].
+
+use std::io;
+
+use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use io_extra::IoErrorExt as _;
+use num_traits::Zero;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+
+use super::{bail, recv_json, send_json, ExchangeMessage, OrderBook, WsError, WsMessage, WsResult};
+
+/// Input channel should NOT have had messages sent over it...
+/// `coin` should be e.g `BTC`.
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    coin: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    stream::once(_protocol(s, coin)).flatten()
+}
+
+/// ```text
+///           ┌───┐          ┌────────┐
+///           │bot│          │exchange│
+///           └─┬─┘          └───┬────┘
+///             │   subscribe    │
+///             │───────────────>│
+///             │                │
+///             │  subscription  │
+///             │    response    │
+///             │<─ ─ ─ ─ ─ ─ ─ ─│
+///             │                │
+/// ╔═══════╤═══╪════════════════╪═══╗
+/// ║ LOOP  │  infinite          │   ║
+/// ╟───────┘   │    snapshot    │   ║
+/// ║           │<───────────────│   ║
+/// ╚═══════════╪════════════════╪═══╝
+///           ┌─┴─┐          ┌───┴────┐
+///           │bot│          │exchange│
+///           └───┘          └────────┘
+/// ```
+///
+/// The first snapshot is emitted in full, and every later one as the
+/// levels that changed, with removed levels at zero quantity.
+async fn _protocol<PriceT, QuantityT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    coin: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero + PartialOrd + Clone,
+{
+    if let Err(e) = send_json(
+        &mut s,
+        json!({"method": "subscribe", "subscription": {"type": "l2Book", "coin": coin}}),
+    )
+    .await
+    {
+        bail!(e)
+    };
+    Either::Right(
+        stream::try_unfold(
+            (s, coin, OrderBook::default()),
+            |(mut s, coin, mut book)| async move {
+                let messages = match recv_json(&mut s).await? {
+                    Message::L2Book {
+                        data:
+                            Book {
+                                coin: it,
+                                levels: Levels(bids, asks),
+                            },
+                    } if it == coin => {
+                        let messages = book.apply_snapshot(
+                            bids.into_iter().map(|Level { px, sz, .. }| (px, sz)),
+                            asks.into_iter().map(|Level { px, sz, .. }| (px, sz)),
+                        );
+                        if book.validate().is_err() {
+                            warn!("snapshot crossed the book")
+                        }
+                        messages
+                    }
+                    Message::Error { data } => {
+                        return Err(WsError::Io(io::Error::invalid_data(format!(
+                            "request failed: {data}"
+                        ))))
+                    }
+                    Message::L2Book { .. } | Message::Other => vec![],
+                };
+                Ok(Some((
+                    stream::iter(messages.into_iter().map(Ok)),
+                    (s, coin, book),
+                )))
+            },
+        )
+        .try_flatten(),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(tag = "channel", rename_all = "camelCase")]
+enum Message<PriceT, QuantityT> {
+    L2Book {
+        data: Book<PriceT, QuantityT>,
+    },
+    Error {
+        data: String,
+    },
+    /// `subscriptionResponse`, `pong` etc.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+struct Book<PriceT, QuantityT> {
+    coin: String,
+    levels: Levels<PriceT, QuantityT>,
+}
+
+/// Bids, best first, then asks, best first.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+struct Levels<PriceT, QuantityT>(Vec<Level<PriceT, QuantityT>>, Vec<Level<PriceT, QuantityT>>);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+struct Level<PriceT, QuantityT> {
+    px: PriceT,
+    sz: QuantityT,
+    /// The number of orders at this level.
+    n: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt as _;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::integrations::{round_trip, u16f16};
+
+    #[test]
+    fn deser() {
+        round_trip(
+            Message::L2Book {
+                data: Book {
+                    coin: "BTC".into(),
+                    levels: Levels(
+                        vec![Level {
+                            px: u16f16::lit("123"),
+                            sz: u16f16::lit("0.5"),
+                            n: 2,
+                        }],
+                        vec![],
+                    ),
+                },
+            },
+            json!({
+                "channel": "l2Book",
+                "data": {
+                    "coin": "BTC",
+                    "levels": [[{"px": "123", "sz": "0.5", "n": 2}], []]
+                }
+            }),
+        );
+        let it = serde_json::from_value::<Message<u16f16, u16f16>>(json!({
+            "channel": "subscriptionResponse",
+            "data": {"method": "subscribe", "subscription": {"type": "l2Book", "coin": "BTC"}}
+        }))
+        .unwrap();
+        assert_eq!(it, Message::Other);
+    }
+
+    #[tokio::test]
+    async fn diffs_snapshots() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let level = |px: &str, sz: &str| json!({"px": px, "sz": sz, "n": 1});
+            for message in [
+                json!({"channel": "subscriptionResponse", "data": {}}),
+                json!({"channel": "l2Book", "data": {"coin": "BTC", "levels": [
+                    [level("10", "1"), level("9", "2")],
+                    [level("11", "1")],
+                ]}}),
+                // 9 is gone, 10 changed size, and 11 is untouched
+                json!({"channel": "l2Book", "data": {"coin": "BTC", "levels": [
+                    [level("10", "3")],
+                    [level("11", "1"), level("12", "4")],
+                ]}}),
+            ] {
+                ws.send(WsMessage::Text(message.to_string())).await.unwrap();
+            }
+            futures::future::pending::<()>().await
+        });
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let messages = protocol::<u16f16, u16f16>(ws, "BTC".into())
+            .take(6)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let buy = |price, quantity| ExchangeMessage::Buy {
+            price: u16f16::lit(price),
+            quantity: u16f16::lit(quantity),
+        };
+        let sell = |price, quantity| ExchangeMessage::Sell {
+            price: u16f16::lit(price),
+            quantity: u16f16::lit(quantity),
+        };
+        assert_eq!(
+            messages,
+            [
+                // the first snapshot in full
+                buy("9", "2"),
+                buy("10", "1"),
+                sell("11", "1"),
+                // then only what changed
+                buy("9", "0"),
+                buy("10", "3"),
+                sell("12", "4"),
+            ]
+        );
+    }
+}
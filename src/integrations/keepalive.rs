@@ -0,0 +1,288 @@
+//! Keep idle connections open, and notice when a feed has gone quiet.
+//!
+//! tungstenite queues a `Pong` for every `Ping` it reads, and flushes it on the
+//! next read or write, so we only need to nudge it along.
+
+use std::{
+    future::Future as _,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Sink, Stream};
+use tokio::time::{Instant, Interval, MissedTickBehavior, Sleep};
+
+use super::{FeedError, WsError, WsMessage, WsResult};
+
+/// Websocket-level liveness settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Keepalive {
+    /// Send a `Ping` this often.
+    ///
+    /// Aevo, for example, closes connections after 15 minutes of inactivity.
+    pub ping_interval: Option<Duration>,
+    /// Fail with [`FeedError::Stale`] if no data is received for this long.
+    ///
+    /// `Ping`s and `Pong`s don't count, so a live connection with a dead
+    /// subscription will still trip this.
+    pub stale_after: Option<Duration>,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            ping_interval: Some(Duration::from_secs(30)),
+            stale_after: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Wraps a websocket to enforce a [`Keepalive`].
+///
+/// Pings are only sent while the stream is being polled.
+pub struct KeepaliveStream<S> {
+    inner: S,
+    ping: Option<Interval>,
+    watchdog: Option<(Duration, Pin<Box<Sleep>>)>,
+    ping_due: bool,
+    flush_due: bool,
+}
+
+impl<S> KeepaliveStream<S> {
+    pub fn new(
+        inner: S,
+        Keepalive {
+            ping_interval,
+            stale_after,
+        }: Keepalive,
+    ) -> Self {
+        Self {
+            inner,
+            ping: ping_interval.map(|period| {
+                // the first tick of `tokio::time::interval` completes immediately
+                let mut it = tokio::time::interval_at(Instant::now() + period, period);
+                it.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                it
+            }),
+            watchdog: stale_after.map(|after| (after, Box::pin(tokio::time::sleep(after)))),
+            ping_due: false,
+            flush_due: false,
+        }
+    }
+}
+
+impl<S> Stream for KeepaliveStream<S>
+where
+    S: Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+{
+    type Item = WsResult<WsMessage>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        if let Some(ping) = &mut this.ping {
+            // a `Ready` tick doesn't register for the next one, so poll until it does
+            while ping.poll_tick(cx).is_ready() {
+                this.ping_due = true
+            }
+        }
+        if this.ping_due {
+            match Pin::new(&mut this.inner).poll_ready(cx) {
+                Poll::Ready(Ok(())) => {
+                    if let Err(e) = Pin::new(&mut this.inner).start_send(WsMessage::Ping(vec![])) {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    this.ping_due = false;
+                    this.flush_due = true;
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => {}
+            }
+        }
+        if this.flush_due {
+            match Pin::new(&mut this.inner).poll_flush(cx) {
+                Poll::Ready(Ok(())) => this.flush_due = false,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => {}
+            }
+        }
+
+        match Pin::new(&mut this.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(message))) => {
+                match &message {
+                    WsMessage::Text(_) | WsMessage::Binary(_) => {
+                        if let Some((after, sleep)) = &mut this.watchdog {
+                            sleep.as_mut().reset(Instant::now() + *after)
+                        }
+                    }
+                    // make sure the queued `Pong` goes out promptly
+                    WsMessage::Ping(_) => this.flush_due = true,
+                    WsMessage::Pong(_) | WsMessage::Close(_) | WsMessage::Frame(_) => {}
+                }
+                return Poll::Ready(Some(Ok(message)));
+            }
+            Poll::Ready(it) => return Poll::Ready(it),
+            Poll::Pending => {}
+        }
+
+        if let Some((after, sleep)) = &mut this.watchdog {
+            if sleep.as_mut().poll(cx).is_ready() {
+                sleep.as_mut().reset(Instant::now() + *after);
+                return Poll::Ready(Some(Err(WsError::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    FeedError::Stale { after: *after },
+                )))));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<S> Sink<WsMessage> for KeepaliveStream<S>
+where
+    S: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    type Error = WsError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<WsResult<()>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+    fn start_send(mut self: Pin<&mut Self>, item: WsMessage) -> WsResult<()> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<WsResult<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<WsResult<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt as _, StreamExt as _};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A server that sends nothing, but reports what it receives.
+    async fn quiet_server() -> (String, futures::channel::mpsc::UnboundedReceiver<WsMessage>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(it)) = ws.next().await {
+                if tx.unbounded_send(it).is_err() {
+                    break;
+                }
+            }
+        });
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn pings() {
+        let (url, mut received) = quiet_server().await;
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let mut ws = KeepaliveStream::new(
+            ws,
+            Keepalive {
+                ping_interval: Some(Duration::from_millis(10)),
+                stale_after: None,
+            },
+        );
+        let client = tokio::spawn(async move { while ws.next().await.is_some() {} });
+        assert!(matches!(received.next().await, Some(WsMessage::Ping(_))));
+        assert!(matches!(received.next().await, Some(WsMessage::Ping(_))));
+        client.abort();
+    }
+
+    #[tokio::test]
+    async fn pings_without_replies() {
+        use tokio::io::AsyncReadExt as _;
+
+        // completes the handshake, then reads raw frames, so never sends a `Pong`
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            // masked, empty `Ping`s from the client
+            let mut frames = [0; 12];
+            ws.get_mut().read_exact(&mut frames).await.unwrap();
+            frames
+        });
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let mut ws = KeepaliveStream::new(
+            ws,
+            Keepalive {
+                ping_interval: Some(Duration::from_millis(10)),
+                stale_after: None,
+            },
+        );
+        let client = tokio::spawn(async move { while ws.next().await.is_some() {} });
+        let frames = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("only one ping was sent")
+            .unwrap();
+        assert_eq!([frames[0], frames[1]], [0x89, 0x80]);
+        assert_eq!([frames[6], frames[7]], [0x89, 0x80]);
+        client.abort();
+    }
+
+    #[tokio::test]
+    async fn stale() {
+        let (url, _received) = quiet_server().await;
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let mut ws = KeepaliveStream::new(
+            ws,
+            Keepalive {
+                ping_interval: Some(Duration::from_millis(10)),
+                stale_after: Some(Duration::from_millis(50)),
+            },
+        );
+        // pongs don't count as data
+        let error = loop {
+            match ws.next().await.unwrap() {
+                Ok(WsMessage::Pong(_)) => continue,
+                it => break it.unwrap_err(),
+            }
+        };
+        assert!(matches!(
+            FeedError::of(&error),
+            Some(FeedError::Stale { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn data_resets_watchdog() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                ws.send(WsMessage::Text("hello".into())).await.unwrap();
+            }
+            // stay connected, but quiet
+            std::future::pending::<()>().await;
+            drop(ws)
+        });
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let ws = KeepaliveStream::new(
+            ws,
+            Keepalive {
+                ping_interval: None,
+                stale_after: Some(Duration::from_millis(60)),
+            },
+        );
+        let received = ws.take(6).collect::<Vec<_>>().await;
+        assert!(received[..5].iter().all(|it| it.is_ok()));
+        assert!(FeedError::of(received[5].as_ref().unwrap_err()).is_some());
+    }
+}
@@ -0,0 +1,365 @@
+//! Kraken's [v2 book channel](https://docs.kraken.com/api/docs/websocket-v2/book)
+//! sends a CRC32 [checksum](https://docs.kraken.com/api/guides/spot-ws-book-v2)
+//! of the top levels with every message, so we keep a copy of the book to check it against.
+//!
+//! Prices and quantities are sent as JSON numbers, see [`JsonNumber`].
+
+use std::io;
+
+use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use io_extra::IoErrorExt as _;
+use num_traits::Zero;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+
+use crate::Side;
+
+use super::{
+    bail, book::TextBook, deserialize_json, recv_raw, send_json, ExchangeMessage, JsonNumber,
+    WsError, WsMessage, WsResult,
+};
+
+/// The number of levels we subscribe to, and that the checksum covers.
+const DEPTH: usize = 10;
+
+/// Input channel should NOT have had messages sent over it...
+/// `symbol` should be e.g `BTC/USD`.
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    symbol: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    stream::once(_protocol(s, symbol)).flatten()
+}
+
+/// ```text
+///           ┌───┐          ┌────────┐
+///           │bot│          │exchange│
+///           └─┬─┘          └───┬────┘
+///             │   subscribe    │
+///             │───────────────>│
+///             │                │
+/// ╔═══════╤═══╪════════════════╪═══════════════════╗
+/// ║ LOOP  │  infinite          │                   ║
+/// ╟───────┘   │    snapshot    │                   ║
+/// ║           │<───────────────│                   ║
+/// ║           │                │                   ║
+/// ║ ╔═══════╤═╪════════════════╪═════════════════╗ ║
+/// ║ ║ LOOP  │  until checksum mismatch           ║ ║
+/// ║ ╟───────┘ │     update     │                 ║ ║
+/// ║ ║         │<─ ─ ─ ─ ─ ─ ─ ─│                 ║ ║
+/// ║ ╚═════════╪════════════════╪═════════════════╝ ║
+/// ║           │  unsubscribe   │                   ║
+/// ║           │───────────────>│                   ║
+/// ║           │   subscribe    │                   ║
+/// ║           │───────────────>│                   ║
+/// ╚═══════════╪════════════════╪═══════════════════╝
+///           ┌─┴─┐          ┌───┴────┐
+///           │bot│          │exchange│
+///           └───┘          └────────┘
+/// ```
+///
+/// Other channels (`status`, `heartbeat`) and acknowledgements are ignored.
+///
+/// On a checksum mismatch, [`ExchangeMessage::Resynced`] is yielded,
+/// and updates are dropped until the fresh snapshot arrives.
+async fn _protocol<PriceT, QuantityT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    symbol: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    if let Err(e) = send_json(&mut s, request("subscribe", &symbol)).await {
+        bail!(e)
+    }
+    let state = State {
+        book: TextBook::default(),
+        awaiting_snapshot: true,
+    };
+    Either::Right(
+        stream::try_unfold(
+            (s, symbol, state),
+            |(mut s, symbol, mut state)| async move {
+                let raw = recv_raw(&mut s).await?;
+                let messages = match deserialize_json::<Header>(&raw)? {
+                    Header {
+                        method: Some(_),
+                        success: Some(false),
+                        error,
+                        ..
+                    } => {
+                        return Err(WsError::Io(io::Error::invalid_data(format!(
+                            "request failed: {}",
+                            error.unwrap_or_default()
+                        ))))
+                    }
+                    Header {
+                        channel: Some(channel),
+                        kind: Some(kind),
+                        ..
+                    } if channel == "book" => {
+                        let Data { data } = deserialize_json::<Data<PriceT, QuantityT>>(&raw)?;
+                        let mut messages = vec![];
+                        for data in data.into_iter().filter(|it| it.symbol == symbol) {
+                            match &*kind {
+                                "snapshot" => {
+                                    if !state.book.is_empty() {
+                                        messages.push(ExchangeMessage::Resynced)
+                                    }
+                                    state.book = TextBook::default();
+                                    state.awaiting_snapshot = false;
+                                }
+                                "update" if state.awaiting_snapshot => continue,
+                                "update" => {}
+                                _ => {
+                                    return Err(WsError::Io(io::Error::invalid_data(format!(
+                                        "unexpected book message type {kind}"
+                                    ))))
+                                }
+                            }
+                            let expected = data.checksum;
+                            apply(&mut state.book, data, &mut messages);
+                            if checksum(&state.book) != expected {
+                                warn!(symbol, "checksum mismatch, resubscribing");
+                                send_json(&mut s, request("unsubscribe", &symbol)).await?;
+                                send_json(&mut s, request("subscribe", &symbol)).await?;
+                                state.book = TextBook::default();
+                                state.awaiting_snapshot = true;
+                                messages.push(ExchangeMessage::Resynced);
+                                break;
+                            }
+                        }
+                        messages
+                    }
+                    _ => vec![],
+                };
+                Ok(Some((
+                    stream::iter(messages.into_iter().map(Ok)),
+                    (s, symbol, state),
+                )))
+            },
+        )
+        .try_flatten(),
+    )
+}
+
+fn request(method: &str, symbol: &str) -> serde_json::Value {
+    json!({
+        "method": method,
+        "params": {"channel": "book", "symbol": [symbol], "depth": DEPTH}
+    })
+}
+
+struct State<PriceT> {
+    book: TextBook<PriceT>,
+    awaiting_snapshot: bool,
+}
+
+/// Apply a snapshot or update, pushing the corresponding messages.
+///
+/// Kraken doesn't send deletes for levels that fall out of the subscribed depth,
+/// so we do.
+fn apply<PriceT, QuantityT>(
+    book: &mut TextBook<PriceT>,
+    BookData { bids, asks, .. }: BookData<PriceT, QuantityT>,
+    messages: &mut Vec<ExchangeMessage<PriceT, QuantityT>>,
+) where
+    PriceT: Ord + Clone,
+    QuantityT: Zero,
+{
+    for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+        for Level { price, qty } in levels {
+            book.apply(
+                side,
+                (price.value, price.text),
+                (qty.value, qty.text),
+                messages,
+            )
+        }
+    }
+    while book.bids.len() > DEPTH {
+        if let Some((price, _)) = book.bids.pop_first() {
+            messages.push(ExchangeMessage::Buy {
+                price,
+                quantity: QuantityT::zero(),
+            })
+        }
+    }
+    while book.asks.len() > DEPTH {
+        if let Some((price, _)) = book.asks.pop_last() {
+            messages.push(ExchangeMessage::Sell {
+                price,
+                quantity: QuantityT::zero(),
+            })
+        }
+    }
+}
+
+/// The top asks, best first, then the top bids, best first,
+/// each as price then quantity with the decimal point and leading zeros removed.
+fn checksum<PriceT>(book: &TextBook<PriceT>) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for (price, qty) in book
+        .asks
+        .values()
+        .take(DEPTH)
+        .chain(book.bids.values().rev().take(DEPTH))
+    {
+        for text in [price, qty] {
+            hasher.update(text.replace('.', "").trim_start_matches('0').as_bytes())
+        }
+    }
+    hasher.finalize()
+}
+
+/// Just enough to decide how to deserialize the rest of a message.
+#[derive(Deserialize, Debug, PartialEq, Eq, Hash, Clone, Default)]
+struct Header {
+    channel: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    method: Option<String>,
+    success: Option<bool>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct Data<PriceT, QuantityT> {
+    data: Vec<BookData<PriceT, QuantityT>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct BookData<PriceT, QuantityT> {
+    symbol: String,
+    bids: Vec<Level<PriceT, QuantityT>>,
+    asks: Vec<Level<PriceT, QuantityT>>,
+    checksum: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct Level<PriceT, QuantityT> {
+    price: JsonNumber<PriceT>,
+    qty: JsonNumber<QuantityT>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::integrations::u16f16;
+
+    fn level(price: &str, qty: &str) -> Level<u16f16, u16f16> {
+        Level {
+            price: JsonNumber {
+                text: price.into(),
+                value: u16f16::from_str(price).unwrap(),
+                quoted: false,
+            },
+            qty: JsonNumber {
+                text: qty.into(),
+                value: u16f16::from_str(qty).unwrap(),
+                quoted: false,
+            },
+        }
+    }
+
+    #[test]
+    fn deser() {
+        let json = json!({
+            "channel": "book",
+            "type": "update",
+            "data": [{
+                "symbol": "BTC/USD",
+                "bids": [{"price": 0.5, "qty": 1.25}],
+                "asks": [],
+                "checksum": 1234,
+            }]
+        });
+        let text = json.to_string();
+        assert_eq!(
+            deserialize_json::<Header>(&Either::Right(text.clone())).unwrap(),
+            Header {
+                channel: Some("book".into()),
+                kind: Some("update".into()),
+                ..Default::default()
+            }
+        );
+        let data = deserialize_json::<Data<u16f16, u16f16>>(&Either::Right(text)).unwrap();
+        assert_eq!(
+            data,
+            Data {
+                data: vec![BookData {
+                    symbol: "BTC/USD".into(),
+                    bids: vec![level("0.5", "1.25")],
+                    asks: vec![],
+                    checksum: 1234,
+                }]
+            }
+        );
+        assert_eq!(serde_json::to_value(&data).unwrap()["data"], json["data"]);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut book = TextBook::default();
+        let mut messages = vec![];
+        apply(
+            &mut book,
+            BookData {
+                symbol: "BTC/USD".into(),
+                bids: vec![level("0.5", "1.50"), level("0.25", "2")],
+                asks: vec![level("1.0", "0.125")],
+                checksum: 0,
+            },
+            &mut messages,
+        );
+        assert_eq!(messages.len(), 3);
+        // "10" "125" "5" "150" "25" "2"
+        assert_eq!(super::checksum(&book), crc32fast::hash(b"101255150252"));
+    }
+
+    #[test]
+    fn truncate() {
+        let mut book = TextBook::default();
+        let mut messages = vec![];
+        apply(
+            &mut book,
+            BookData {
+                symbol: "BTC/USD".into(),
+                bids: (1..=DEPTH + 1)
+                    .map(|it| level(&it.to_string(), "1"))
+                    .collect(),
+                asks: vec![],
+                checksum: 0,
+            },
+            &mut messages,
+        );
+        assert_eq!(book.bids.len(), DEPTH);
+        assert_eq!(
+            messages.last(),
+            Some(&ExchangeMessage::Buy {
+                price: u16f16::lit("1"),
+                quantity: u16f16::ZERO
+            })
+        );
+    }
+}
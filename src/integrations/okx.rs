@@ -0,0 +1,325 @@
+//! OKX's [`books` channel](https://www.okx.com/docs-v5/en/#order-book-trading-market-data-ws-order-book-channel).
+//!
+//! Each message links to the previous one with `seqId`/`prevSeqId`,
+//! and carries a [checksum](https://www.okx.com/docs-v5/en/#overview-websocket-checksum)
+//! of the top levels, computed over the text of each number, see [`JsonNumber`].
+
+use std::io;
+
+use futures::{future::Either, stream, Sink, Stream, StreamExt as _, TryStreamExt as _};
+use io_extra::IoErrorExt as _;
+use num_traits::Zero;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+
+use super::{
+    bail, book::TextBook, recv_json, send_json, ExchangeMessage, JsonNumber, WsError, WsMessage,
+    WsResult,
+};
+use crate::Side;
+
+/// The number of levels on each side that the checksum covers.
+const CHECKSUM_DEPTH: usize = 25;
+
+/// Input channel should NOT have had messages sent over it...
+/// `inst_id` should be e.g `BTC-USDT`.
+pub fn protocol<PriceT, QuantityT>(
+    s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    inst_id: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    stream::once(_protocol(s, inst_id)).flatten()
+}
+
+/// ```text
+///           ┌───┐          ┌────────┐
+///           │bot│          │exchange│
+///           └─┬─┘          └───┬────┘
+///             │   subscribe    │
+///             │───────────────>│
+///             │                │
+///             │   subscribed   │
+///             │<─ ─ ─ ─ ─ ─ ─ ─│
+///             │                │
+/// ╔═══════╤═══╪════════════════╪═════════════════════════════╗
+/// ║ LOOP  │  infinite          │                             ║
+/// ╟───────┘   │    snapshot    │                             ║
+/// ║           │<───────────────│                             ║
+/// ║           │                │                             ║
+/// ║ ╔═══════╤═╪════════════════╪═══════════════════════════╗ ║
+/// ║ ║ LOOP  │  until checksum or sequence mismatch         ║ ║
+/// ║ ╟───────┘ │     update     │                           ║ ║
+/// ║ ║         │<─ ─ ─ ─ ─ ─ ─ ─│                           ║ ║
+/// ║ ╚═════════╪════════════════╪═══════════════════════════╝ ║
+/// ║           │  unsubscribe   │                             ║
+/// ║           │───────────────>│                             ║
+/// ║           │   subscribe    │                             ║
+/// ║           │───────────────>│                             ║
+/// ╚═══════════╪════════════════╪═════════════════════════════╝
+///           ┌─┴─┐          ┌───┴────┐
+///           │bot│          │exchange│
+///           └───┘          └────────┘
+/// ```
+///
+/// On a mismatch, [`ExchangeMessage::Resynced`] is yielded,
+/// and updates are dropped until the fresh snapshot arrives.
+async fn _protocol<PriceT, QuantityT>(
+    mut s: impl Stream<Item = WsResult<WsMessage>> + Sink<WsMessage, Error = WsError> + Unpin,
+    inst_id: String,
+) -> impl Stream<Item = WsResult<ExchangeMessage<PriceT, QuantityT>>>
+where
+    PriceT: DeserializeOwned + Ord + Clone,
+    QuantityT: DeserializeOwned + Zero,
+{
+    if let Err(e) = send_json(&mut s, request("subscribe", &inst_id)).await {
+        bail!(e)
+    }
+    let state = State {
+        book: TextBook::default(),
+        seq_id: None,
+    };
+    Either::Right(
+        stream::try_unfold(
+            (s, inst_id, state),
+            |(mut s, inst_id, mut state)| async move {
+                let Push {
+                    event,
+                    msg,
+                    arg,
+                    action,
+                    data,
+                } = recv_json::<Push<PriceT, QuantityT>>(&mut s).await?;
+                if event.as_deref() == Some("error") {
+                    return Err(WsError::Io(io::Error::invalid_data(format!(
+                        "request failed: {}",
+                        msg.unwrap_or_default()
+                    ))));
+                }
+                let mut messages = vec![];
+                let ours = arg.is_some_and(|it| it.channel == "books" && it.inst_id == inst_id);
+                for data in data.into_iter().filter(|_| ours) {
+                    match action {
+                        Some(Action::Snapshot) => {
+                            if !state.book.is_empty() {
+                                messages.push(ExchangeMessage::Resynced)
+                            }
+                            state.book = TextBook::default();
+                        }
+                        Some(Action::Update) if state.seq_id.is_none() => continue,
+                        Some(Action::Update) | None => {}
+                    }
+                    let in_sequence = match (action, state.seq_id) {
+                        (Some(Action::Update) | None, Some(last)) => data.prev_seq_id == last,
+                        _ => true,
+                    };
+                    let expected = data.checksum;
+                    state.seq_id = Some(data.seq_id);
+                    for (side, levels) in [(Side::Bid, data.bids), (Side::Ask, data.asks)] {
+                        for Level(price, size, _, _) in levels {
+                            state.book.apply(
+                                side,
+                                (price.value, price.text),
+                                (size.value, size.text),
+                                &mut messages,
+                            )
+                        }
+                    }
+                    if !in_sequence || checksum(&state.book) != expected {
+                        warn!(inst_id, in_sequence, "book out of sync, resubscribing");
+                        send_json(&mut s, request("unsubscribe", &inst_id)).await?;
+                        send_json(&mut s, request("subscribe", &inst_id)).await?;
+                        state.book = TextBook::default();
+                        state.seq_id = None;
+                        messages.push(ExchangeMessage::Resynced);
+                        break;
+                    }
+                }
+                Ok(Some((
+                    stream::iter(messages.into_iter().map(Ok)),
+                    (s, inst_id, state),
+                )))
+            },
+        )
+        .try_flatten(),
+    )
+}
+
+fn request(op: &str, inst_id: &str) -> serde_json::Value {
+    json!({"op": op, "args": [{"channel": "books", "instId": inst_id}]})
+}
+
+struct State<PriceT> {
+    book: TextBook<PriceT>,
+    /// Of the last message, or [`None`] if we're waiting for a snapshot.
+    seq_id: Option<i64>,
+}
+
+/// The top bids and asks, best first and interleaved, as `price:size`,
+/// all joined with `:`.
+fn checksum<PriceT>(book: &TextBook<PriceT>) -> i32 {
+    let mut bids = book.bids.values().rev().take(CHECKSUM_DEPTH);
+    let mut asks = book.asks.values().take(CHECKSUM_DEPTH);
+    let mut fields = vec![];
+    loop {
+        let (bid, ask) = (bids.next(), asks.next());
+        if bid.is_none() && ask.is_none() {
+            break;
+        }
+        for (price, size) in bid.into_iter().chain(ask) {
+            fields.extend([price.as_str(), size.as_str()])
+        }
+    }
+    crc32fast::hash(fields.join(":").as_bytes()) as i32
+}
+
+/// Every message we might receive, which are distinguished by which fields are present.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct Push<PriceT, QuantityT> {
+    /// E.g `subscribe` or `error`, for responses to requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    event: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    msg: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arg: Option<Arg>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    action: Option<Action>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    data: Vec<BookData<PriceT, QuantityT>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Arg {
+    channel: String,
+    inst_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Snapshot,
+    Update,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(
+    rename_all = "camelCase",
+    bound(
+        deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+        serialize = ""
+    )
+)]
+struct BookData<PriceT, QuantityT> {
+    asks: Vec<Level<PriceT, QuantityT>>,
+    bids: Vec<Level<PriceT, QuantityT>>,
+    checksum: i32,
+    /// `-1` on snapshots.
+    prev_seq_id: i64,
+    seq_id: i64,
+}
+
+/// `[price, size, liquidated orders (deprecated), number of orders]`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[serde(bound(
+    deserialize = "PriceT: Deserialize<'de>, QuantityT: Deserialize<'de>",
+    serialize = ""
+))]
+struct Level<PriceT, QuantityT>(JsonNumber<PriceT>, JsonNumber<QuantityT>, String, String);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::integrations::{round_trip, u16f16};
+
+    fn decimal(text: &str) -> JsonNumber<u16f16> {
+        JsonNumber {
+            text: text.into(),
+            value: u16f16::from_str(text).unwrap(),
+            quoted: true,
+        }
+    }
+
+    #[test]
+    fn deser() {
+        round_trip(
+            Push {
+                event: None,
+                msg: None,
+                arg: Some(Arg {
+                    channel: "books".into(),
+                    inst_id: "BTC-USDT".into(),
+                }),
+                action: Some(Action::Snapshot),
+                data: vec![BookData {
+                    asks: vec![Level(
+                        decimal("8476.5"),
+                        decimal("415"),
+                        "0".into(),
+                        "13".into(),
+                    )],
+                    bids: vec![],
+                    checksum: -855196043,
+                    prev_seq_id: -1,
+                    seq_id: 123456,
+                }],
+            },
+            json!({
+                "arg": {"channel": "books", "instId": "BTC-USDT"},
+                "action": "snapshot",
+                "data": [{
+                    "asks": [["8476.5", "415", "0", "13"]],
+                    "bids": [],
+                    "checksum": -855196043,
+                    "prevSeqId": -1,
+                    "seqId": 123456
+                }]
+            }),
+        );
+        round_trip(
+            Push::<u16f16, u16f16> {
+                event: Some("error".into()),
+                msg: Some("Invalid request".into()),
+                arg: None,
+                action: None,
+                data: vec![],
+            },
+            json!({"event": "error", "msg": "Invalid request"}),
+        );
+    }
+
+    #[test]
+    fn checksum() {
+        let mut book = TextBook::default();
+        let mut messages = vec![];
+        for (side, price, size) in [
+            (Side::Bid, "3366.1", "7"),
+            (Side::Bid, "3366", "6"),
+            (Side::Ask, "3366.8", "9"),
+            (Side::Ask, "3368", "8"),
+            (Side::Ask, "3372", "8"),
+        ] {
+            let (price, size) = (decimal(price), decimal(size));
+            book.apply(
+                side,
+                (price.value, price.text),
+                (size.value, size.text),
+                &mut messages,
+            )
+        }
+        assert_eq!(
+            super::checksum(&book),
+            crc32fast::hash(b"3366.1:7:3366.8:9:3366:6:3368:8:3372:8") as i32
+        );
+    }
+}
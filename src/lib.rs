@@ -1,23 +1,109 @@
 use std::{
-    collections::{btree_map::Entry as TreeEntry, hash_map::Entry as HashEntry, BTreeMap, HashMap},
+    cmp,
+    collections::{
+        btree_map::Entry as TreeEntry, hash_map::Entry as HashEntry, BTreeMap, BTreeSet, HashMap,
+    },
     hash::{BuildHasher, Hash, RandomState},
     iter,
+    ops::{Div, Mul, Sub},
 };
 
 use itertools::Either;
-use num_traits::Zero;
+use num_traits::{CheckedSub, NumOps, One, Zero};
 
+#[cfg(feature = "serde")]
+mod checkpoint;
+pub mod cycles;
 pub mod integrations;
+pub mod markets;
 
 /// Keeps track of arbitrage opportunities across exchanges.
 /// - Generic over value types - bring your own numbers.
 /// - Wide - supports an arbitrary number of exchanges, with a pluggable hasher for speed.
+/// - Fee-aware - only opportunities that are profitable after [`Fees`] are reported.
+/// - Checkpointable - implements `Serialize` and `Deserialize` with the `serde` feature.
 #[derive(Debug, Clone)]
 pub struct ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT = RandomState> {
     #[doc(alias = "buys")]
-    bids: BTreeMap<PriceT, HashMap<ExchangeIdT, QuantityT, BuildHasherT>>,
+    bids: Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
     #[doc(alias = "sells")]
-    asks: BTreeMap<PriceT, HashMap<ExchangeIdT, QuantityT, BuildHasherT>>,
+    asks: Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+    fees: HashMap<ExchangeIdT, Fees<PriceT>, BuildHasherT>,
+}
+
+#[derive(Debug, Clone)]
+struct Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT> {
+    levels: BTreeMap<PriceT, HashMap<ExchangeIdT, QuantityT, BuildHasherT>>,
+    /// Reverse index of `levels`, kept in sync by [`insert`], [`remove_price_from_exchange`]
+    /// and [`remove_exchange_from_side`].
+    by_exchange: HashMap<ExchangeIdT, Resting<QuantityT, PriceT>, BuildHasherT>,
+}
+
+/// What one exchange has on one [`Half`].
+#[derive(Debug, Clone)]
+struct Resting<QuantityT, PriceT> {
+    prices: BTreeSet<PriceT>,
+    /// The sum of the quantities at [`Self::prices`].
+    depth: QuantityT,
+}
+
+/// Fee rates for an exchange, as a fraction of notional, e.g `0.0005` for 5bps.
+///
+/// Exchanges without registered fees are assumed to be free to trade on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fees<RateT> {
+    /// Charged when adding liquidity to the book.
+    pub maker: RateT,
+    /// Charged when removing liquidity from the book.
+    ///
+    /// Arbitrage opportunities cross resting orders on both exchanges,
+    /// so this is the rate used by [`ArbitrageFinder`].
+    pub taker: RateT,
+}
+
+/// A resting order on another exchange that can be traded against at a profit.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Opportunity<'a, QuantityT, PriceT, ExchangeIdT> {
+    pub exchange_id: &'a ExchangeIdT,
+    pub price: &'a PriceT,
+    pub quantity: &'a QuantityT,
+    /// Bid less ask, per unit.
+    pub gross_spread: PriceT,
+    /// [`Self::gross_spread`] less taker fees on both legs, per unit.
+    ///
+    /// This is always positive.
+    pub net_spread: PriceT,
+}
+
+/// A bid on one exchange that is higher than an ask on another,
+/// see [`ArbitrageFinder::crossings`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Crossing<'a, QuantityT, PriceT, ExchangeIdT> {
+    pub bid_exchange_id: &'a ExchangeIdT,
+    pub bid_price: &'a PriceT,
+    pub bid_quantity: &'a QuantityT,
+    pub ask_exchange_id: &'a ExchangeIdT,
+    pub ask_price: &'a PriceT,
+    pub ask_quantity: &'a QuantityT,
+    /// The smaller of [`Self::bid_quantity`] and [`Self::ask_quantity`].
+    pub quantity: QuantityT,
+    /// See [`Opportunity::gross_spread`].
+    pub gross_spread: PriceT,
+    /// See [`Opportunity::net_spread`].
+    pub net_spread: PriceT,
+    /// [`Self::net_spread`] times [`Self::quantity`].
+    pub profit: PriceT,
+}
+
+/// One half of a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Side {
+    #[doc(alias = "buy")]
+    Bid,
+    #[doc(alias = "sell")]
+    Ask,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -32,11 +118,19 @@ pub enum Error<ExchangeIdT> {
 impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
     ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
 where
-    PriceT: Ord + Clone,
-    QuantityT: Zero,
+    PriceT: Ord + Clone + NumOps,
+    QuantityT: Zero + Clone + Sub<Output = QuantityT>,
     ExchangeIdT: Eq + Hash + Clone,
     BuildHasherT: BuildHasher + Default,
 {
+    /// Register the [`Fees`] for trading on an exchange, returning the previous ones.
+    pub fn set_fees(
+        &mut self,
+        exchange_id: ExchangeIdT,
+        fees: Fees<PriceT>,
+    ) -> Option<Fees<PriceT>> {
+        self.fees.insert(exchange_id, fees)
+    }
     /// Returns sell orders on other exchanges that are arbitrage opportunities.
     ///
     /// The special price of 0 indicates that a price was removed on an exchange.
@@ -46,16 +140,14 @@ where
         exchange_id: ExchangeIdT,
         price: PriceT,
         quantity: QuantityT,
-    ) -> Result<impl Iterator<Item = (&ExchangeIdT, &PriceT, &QuantityT)>, Error<ExchangeIdT>> {
+    ) -> Result<
+        impl Iterator<Item = Opportunity<'_, QuantityT, PriceT, ExchangeIdT>>,
+        Error<ExchangeIdT>,
+    > {
         match quantity.is_zero() {
             false => {
                 insert(&mut self.bids, price.clone(), exchange_id.clone(), quantity);
-                let arbitrages = self
-                    .asks
-                    .iter() // cheapest first
-                    .take_while(move |(ask, _)| *ask < &price)
-                    .flat_map(|(ask, xcs)| xcs.iter().map(move |(xc, q)| (xc, ask, q)))
-                    .filter(move |(xc, _, _)| *xc != &exchange_id);
+                let arbitrages = self.crossed_asks(exchange_id, price);
                 Ok(Either::Left(arbitrages))
             }
             true => remove_price_from_exchange(&mut self.bids, price, exchange_id)
@@ -72,61 +164,478 @@ where
         exchange_id: ExchangeIdT,
         price: PriceT,
         quantity: QuantityT,
-    ) -> Result<impl Iterator<Item = (&ExchangeIdT, &PriceT, &QuantityT)>, Error<ExchangeIdT>> {
+    ) -> Result<
+        impl Iterator<Item = Opportunity<'_, QuantityT, PriceT, ExchangeIdT>>,
+        Error<ExchangeIdT>,
+    > {
         match quantity.is_zero() {
             false => {
                 insert(&mut self.asks, price.clone(), exchange_id.clone(), quantity);
-                let arbitrages = self
-                    .bids
-                    .iter()
-                    .rev() // most generous first
-                    .take_while(move |(bid, _)| *bid > &price)
-                    .flat_map(|(bid, xcs)| xcs.iter().map(move |(xc, q)| (xc, bid, q)))
-                    .filter(move |(xc, _, _)| *xc != &exchange_id);
+                let arbitrages = self.crossed_bids(exchange_id, price);
                 Ok(Either::Left(arbitrages))
             }
-            true => remove_price_from_exchange(&mut self.bids, price, exchange_id)
+            true => remove_price_from_exchange(&mut self.asks, price, exchange_id)
                 .map(Err)
                 .unwrap_or(Ok(Either::Right(iter::empty()))),
         }
     }
+    /// Replace everything `exchange_id` has on `side` with `levels`, e.g when
+    /// receiving a snapshot.
+    ///
+    /// Levels with a quantity of zero are ignored.
+    #[doc(alias = "snapshot")]
+    pub fn replace(
+        &mut self,
+        exchange_id: ExchangeIdT,
+        side: Side,
+        levels: impl IntoIterator<Item = (PriceT, QuantityT)>,
+    ) {
+        let side = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        remove_exchange_from_side(side, &exchange_id);
+        for (price, quantity) in levels {
+            if !quantity.is_zero() {
+                insert(side, price, exchange_id.clone(), quantity)
+            }
+        }
+    }
+    /// Remove every bid and ask that `exchange_id` has, e.g when its feed disconnects.
+    pub fn remove_exchange(&mut self, exchange_id: &ExchangeIdT) {
+        remove_exchange_from_side(&mut self.bids, exchange_id);
+        remove_exchange_from_side(&mut self.asks, exchange_id);
+    }
+    /// Scan the whole book for bids on one exchange that can be profitably sold
+    /// to from an ask on another, largest [`Crossing::profit`] first.
+    ///
+    /// Unlike [`Self::buy`] and [`Self::sell`], this includes opportunities that
+    /// weren't caused by the latest update.
+    /// Note that the same level may appear in several [`Crossing`]s.
+    pub fn crossings(&self) -> Vec<Crossing<'_, QuantityT, PriceT, ExchangeIdT>>
+    where
+        QuantityT: Ord + Clone,
+        PriceT: Mul<QuantityT, Output = PriceT>,
+    {
+        let Some((best_ask, _)) = self.asks.levels.first_key_value() else {
+            return vec![];
+        };
+        let mut crossings = vec![];
+        for (bid, bidders) in self
+            .bids
+            .levels
+            .iter()
+            .rev()
+            .take_while(|(bid, _)| *bid > best_ask)
+        {
+            for (ask, askers) in self.asks.levels.iter().take_while(|(ask, _)| *ask < bid) {
+                for (bid_exchange_id, bid_quantity) in bidders {
+                    for (ask_exchange_id, ask_quantity) in askers {
+                        if bid_exchange_id == ask_exchange_id {
+                            continue;
+                        }
+                        if let Some(Opportunity {
+                            gross_spread,
+                            net_spread,
+                            ..
+                        }) = opportunity(
+                            ask_exchange_id,
+                            ask,
+                            ask_quantity,
+                            (bid, self.fees.get(bid_exchange_id)),
+                            (ask, self.fees.get(ask_exchange_id)),
+                        ) {
+                            let quantity = cmp::min(bid_quantity, ask_quantity).clone();
+                            crossings.push(Crossing {
+                                bid_exchange_id,
+                                bid_price: bid,
+                                bid_quantity,
+                                ask_exchange_id,
+                                ask_price: ask,
+                                ask_quantity,
+                                profit: net_spread.clone() * quantity.clone(),
+                                quantity,
+                                gross_spread,
+                                net_spread,
+                            })
+                        }
+                    }
+                }
+            }
+        }
+        crossings.sort_by(|left, right| {
+            (&right.profit, &right.net_spread).cmp(&(&left.profit, &left.net_spread))
+        });
+        crossings
+    }
+    /// Asks on other exchanges that are arbitrage opportunities for a bid, cheapest first.
+    fn crossed_asks(
+        &self,
+        exchange_id: ExchangeIdT,
+        price: PriceT,
+    ) -> impl Iterator<Item = Opportunity<'_, QuantityT, PriceT, ExchangeIdT>> {
+        let fees = &self.fees;
+        let bid_fees = fees.get(&exchange_id);
+        self.asks
+            .levels
+            .iter() // cheapest first
+            .take_while({
+                let price = price.clone();
+                move |(ask, _)| *ask < &price
+            })
+            .flat_map(|(ask, xcs)| xcs.iter().map(move |(xc, q)| (xc, ask, q)))
+            .filter(move |(xc, _, _)| *xc != &exchange_id)
+            .filter_map(move |(xc, ask, q)| {
+                opportunity(xc, ask, q, (&price, bid_fees), (ask, fees.get(xc)))
+            })
+    }
+    /// Bids on other exchanges that are arbitrage opportunities for an ask, most generous first.
+    fn crossed_bids(
+        &self,
+        exchange_id: ExchangeIdT,
+        price: PriceT,
+    ) -> impl Iterator<Item = Opportunity<'_, QuantityT, PriceT, ExchangeIdT>> {
+        let fees = &self.fees;
+        let ask_fees = fees.get(&exchange_id);
+        self.bids
+            .levels
+            .iter()
+            .rev() // most generous first
+            .take_while({
+                let price = price.clone();
+                move |(bid, _)| *bid > &price
+            })
+            .flat_map(|(bid, xcs)| xcs.iter().map(move |(xc, q)| (xc, bid, q)))
+            .filter(move |(xc, _, _)| *xc != &exchange_id)
+            .filter_map(move |(xc, bid, q)| {
+                opportunity(xc, bid, q, (bid, fees.get(xc)), (&price, ask_fees))
+            })
+    }
+}
+
+impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+    ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    PriceT: Ord
+        + Clone
+        + NumOps
+        + Zero
+        + Mul<QuantityT, Output = PriceT>
+        + Div<QuantityT, Output = PriceT>,
+    QuantityT: Ord + Clone + Zero + Sub<Output = QuantityT>,
+    ExchangeIdT: Eq + Hash + Clone,
+    BuildHasherT: BuildHasher + Default,
+{
+    /// Plan how to sell `quantity` to a bid at `price` on `exchange_id`,
+    /// buying from as many asks on other exchanges as are profitable.
+    ///
+    /// Returns [`None`] if there are no arbitrage opportunities.
+    ///
+    /// This does not modify the book - see [`Self::buy`].
+    #[doc(alias = "plan_bid")]
+    pub fn plan_buy(
+        &self,
+        exchange_id: ExchangeIdT,
+        price: PriceT,
+        quantity: QuantityT,
+    ) -> Option<Plan<QuantityT, PriceT, ExchangeIdT>> {
+        let Walk {
+            quantity,
+            price: buy_price,
+            profit,
+            fills,
+        } = walk(self.crossed_asks(exchange_id, price.clone()), quantity)?;
+        Some(Plan {
+            quantity,
+            buy_price,
+            sell_price: price,
+            profit,
+            fills,
+        })
+    }
+    /// Plan how to buy `quantity` from an ask at `price` on `exchange_id`,
+    /// selling to as many bids on other exchanges as are profitable.
+    ///
+    /// Returns [`None`] if there are no arbitrage opportunities.
+    ///
+    /// This does not modify the book - see [`Self::sell`].
+    #[doc(alias = "plan_ask")]
+    pub fn plan_sell(
+        &self,
+        exchange_id: ExchangeIdT,
+        price: PriceT,
+        quantity: QuantityT,
+    ) -> Option<Plan<QuantityT, PriceT, ExchangeIdT>> {
+        let Walk {
+            quantity,
+            price: sell_price,
+            profit,
+            fills,
+        } = walk(self.crossed_bids(exchange_id, price.clone()), quantity)?;
+        Some(Plan {
+            quantity,
+            buy_price: price,
+            sell_price,
+            profit,
+            fills,
+        })
+    }
+}
+
+/// Read-only queries on the consolidated book.
+impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+    ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    PriceT: Ord,
+    ExchangeIdT: Eq + Hash,
+    BuildHasherT: BuildHasher,
+{
+    /// The highest bid across all exchanges, with each exchange's quantity at that price.
+    pub fn best_bid(&self) -> Option<(&PriceT, &HashMap<ExchangeIdT, QuantityT, BuildHasherT>)> {
+        self.levels(Side::Bid).next()
+    }
+    /// The lowest ask across all exchanges, with each exchange's quantity at that price.
+    pub fn best_ask(&self) -> Option<(&PriceT, &HashMap<ExchangeIdT, QuantityT, BuildHasherT>)> {
+        self.levels(Side::Ask).next()
+    }
+    /// Halfway between [`Self::best_bid`] and [`Self::best_ask`].
+    pub fn mid(&self) -> Option<PriceT>
+    where
+        PriceT: Clone + NumOps + One,
+    {
+        Some(mid(self.best_bid()?.0, self.best_ask()?.0))
+    }
+    /// [`Self::best_ask`] less [`Self::best_bid`].
+    ///
+    /// Returns [`None`] if either side is empty, or if the spread isn't representable,
+    /// e.g the book is crossed and `PriceT` is unsigned.
+    pub fn spread(&self) -> Option<PriceT>
+    where
+        PriceT: CheckedSub,
+    {
+        self.best_ask()?.0.checked_sub(self.best_bid()?.0)
+    }
+    /// Price levels on `side`, best first, with each exchange's quantity at that price.
+    pub fn levels(
+        &self,
+        side: Side,
+    ) -> impl Iterator<Item = (&PriceT, &HashMap<ExchangeIdT, QuantityT, BuildHasherT>)> {
+        match side {
+            Side::Bid => Either::Left(self.bids.levels.iter().rev()),
+            Side::Ask => Either::Right(self.asks.levels.iter()),
+        }
+    }
+    /// What `exchange_id` is currently showing.
+    pub fn exchange<'a>(
+        &'a self,
+        exchange_id: &'a ExchangeIdT,
+    ) -> ExchangeBook<'a, QuantityT, PriceT, ExchangeIdT, BuildHasherT> {
+        ExchangeBook {
+            finder: self,
+            exchange_id,
+        }
+    }
+    /// Every exchange with at least one level, in arbitrary order.
+    pub fn exchanges(&self) -> impl Iterator<Item = &ExchangeIdT> {
+        let bidders = self.bids.by_exchange.keys();
+        let askers = self
+            .asks
+            .by_exchange
+            .keys()
+            .filter(|it| !self.bids.by_exchange.contains_key(*it));
+        bidders.chain(askers)
+    }
+    /// The [`Fees`] registered for `exchange_id`, see [`Self::set_fees`].
+    pub fn fees(&self, exchange_id: &ExchangeIdT) -> Option<&Fees<PriceT>> {
+        self.fees.get(exchange_id)
+    }
+}
+
+fn mid<PriceT>(bid: &PriceT, ask: &PriceT) -> PriceT
+where
+    PriceT: Clone + NumOps + One,
+{
+    (bid.clone() + ask.clone()) / (PriceT::one() + PriceT::one())
+}
+
+/// The result of walking the opposing side of the book for a single order.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Plan<QuantityT, PriceT, ExchangeIdT> {
+    /// Total quantity that can be profitably crossed.
+    pub quantity: QuantityT,
+    /// Volume-weighted price paid on the buy leg.
+    pub buy_price: PriceT,
+    /// Volume-weighted price received on the sell leg.
+    pub sell_price: PriceT,
+    /// Sum of net spread over [`Self::fills`].
+    pub profit: PriceT,
+    /// Resting orders to trade against, best net spread first.
+    pub fills: Vec<Fill<QuantityT, PriceT, ExchangeIdT>>,
+}
+
+/// Part of a [`Plan`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fill<QuantityT, PriceT, ExchangeIdT> {
+    pub exchange_id: ExchangeIdT,
+    pub price: PriceT,
+    pub quantity: QuantityT,
+}
+
+struct Walk<QuantityT, PriceT, ExchangeIdT> {
+    quantity: QuantityT,
+    /// Volume-weighted.
+    price: PriceT,
+    profit: PriceT,
+    fills: Vec<Fill<QuantityT, PriceT, ExchangeIdT>>,
+}
+
+/// Take from `opportunities`, best net spread first, until `quantity` is exhausted.
+///
+/// Price order isn't enough, since a better price can be on an exchange with higher fees.
+fn walk<'a, QuantityT, PriceT, ExchangeIdT>(
+    opportunities: impl Iterator<Item = Opportunity<'a, QuantityT, PriceT, ExchangeIdT>>,
+    quantity: QuantityT,
+) -> Option<Walk<QuantityT, PriceT, ExchangeIdT>>
+where
+    PriceT:
+        Ord + Clone + Zero + Mul<QuantityT, Output = PriceT> + Div<QuantityT, Output = PriceT> + 'a,
+    QuantityT: Ord + Clone + Zero + Sub<Output = QuantityT> + 'a,
+    ExchangeIdT: Clone + 'a,
+{
+    let mut opportunities = opportunities.collect::<Vec<_>>();
+    // stable, so ties stay in price order
+    opportunities.sort_by(|left, right| right.net_spread.cmp(&left.net_spread));
+    let mut remaining = quantity;
+    let mut filled = QuantityT::zero();
+    let mut notional = PriceT::zero();
+    let mut profit = PriceT::zero();
+    let mut fills = vec![];
+    for Opportunity {
+        exchange_id,
+        price,
+        quantity,
+        net_spread,
+        ..
+    } in opportunities
+    {
+        if remaining.is_zero() {
+            break;
+        }
+        let quantity = cmp::min(remaining.clone(), quantity.clone());
+        remaining = remaining - quantity.clone();
+        filled = filled + quantity.clone();
+        notional = notional + price.clone() * quantity.clone();
+        profit = profit + net_spread * quantity.clone();
+        fills.push(Fill {
+            exchange_id: exchange_id.clone(),
+            price: price.clone(),
+            quantity,
+        });
+    }
+    match filled.is_zero() {
+        true => None,
+        false => Some(Walk {
+            price: notional / filled.clone(),
+            quantity: filled,
+            profit,
+            fills,
+        }),
+    }
+}
+
+/// `bid` and `ask` are each a price and the fees of the exchange it is on.
+fn opportunity<'a, QuantityT, PriceT, ExchangeIdT>(
+    exchange_id: &'a ExchangeIdT,
+    price: &'a PriceT,
+    quantity: &'a QuantityT,
+    (bid, bid_fees): (&PriceT, Option<&Fees<PriceT>>),
+    (ask, ask_fees): (&PriceT, Option<&Fees<PriceT>>),
+) -> Option<Opportunity<'a, QuantityT, PriceT, ExchangeIdT>>
+where
+    PriceT: Ord + Clone + NumOps,
+{
+    let proceeds = match bid_fees {
+        Some(Fees { taker, .. }) => bid.clone() - bid.clone() * taker.clone(),
+        None => bid.clone(),
+    };
+    let cost = match ask_fees {
+        Some(Fees { taker, .. }) => ask.clone() + ask.clone() * taker.clone(),
+        None => ask.clone(),
+    };
+    // compare before subtracting, so that unsigned prices don't underflow
+    match proceeds > cost {
+        true => Some(Opportunity {
+            exchange_id,
+            price,
+            quantity,
+            gross_spread: bid.clone() - ask.clone(),
+            net_spread: proceeds - cost,
+        }),
+        false => None,
+    }
 }
 
 fn insert<QuantityT, PriceT, ExchangeIdT, BuildHasherT>(
-    side: &mut BTreeMap<PriceT, HashMap<ExchangeIdT, QuantityT, BuildHasherT>>,
+    side: &mut Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
     price: PriceT,
     exchange_id: ExchangeIdT,
     quantity: QuantityT,
 ) where
-    PriceT: Ord,
+    PriceT: Ord + Clone,
+    QuantityT: Zero + Clone + Sub<Output = QuantityT>,
     BuildHasherT: Default + BuildHasher,
-    ExchangeIdT: Hash + Eq,
+    ExchangeIdT: Hash + Eq + Clone,
 {
+    let resting = side
+        .by_exchange
+        .entry(exchange_id.clone())
+        .or_insert_with(|| Resting {
+            prices: BTreeSet::new(),
+            depth: QuantityT::zero(),
+        });
+    resting.prices.insert(price.clone());
+    resting.depth = resting.depth.clone() + quantity.clone();
     // matching here allows us to `entry(..).and_modify(..).or_insert(..)` without adding a bunch of `Clone` bounds
-    match side.entry(price) {
+    let previous = match side.levels.entry(price) {
         TreeEntry::Vacant(it) => it
             .insert(HashMap::with_hasher(BuildHasherT::default()))
             .insert(exchange_id, quantity),
         TreeEntry::Occupied(mut it) => it.get_mut().insert(exchange_id, quantity),
     };
+    if let Some(previous) = previous {
+        resting.depth = resting.depth.clone() - previous;
+    }
 }
 
 fn remove_price_from_exchange<QuantityT, PriceT, ExchangeIdT, BuildHasherT>(
-    side: &mut BTreeMap<PriceT, HashMap<ExchangeIdT, QuantityT, BuildHasherT>>,
+    side: &mut Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
     price: PriceT,
     exchange_id: ExchangeIdT,
 ) -> Option<Error<ExchangeIdT>>
 where
     PriceT: Ord,
+    QuantityT: Clone + Sub<Output = QuantityT>,
     BuildHasherT: BuildHasher,
     ExchangeIdT: Eq + Hash,
 {
-    match side.entry(price) {
+    let Half {
+        levels,
+        by_exchange,
+    } = side;
+    match levels.entry(price) {
         TreeEntry::Vacant(_) => Some(Error::Needless { exchange_id }),
         TreeEntry::Occupied(mut price_level) => {
             let err = match price_level.get_mut().entry(exchange_id) {
                 HashEntry::Occupied(exchange) => {
-                    exchange.remove();
+                    let (exchange_id, quantity) = exchange.remove_entry();
+                    if let HashEntry::Occupied(mut resting) = by_exchange.entry(exchange_id) {
+                        let Resting { prices, depth } = resting.get_mut();
+                        prices.remove(price_level.key());
+                        *depth = depth.clone() - quantity;
+                        if prices.is_empty() {
+                            resting.remove();
+                        }
+                    }
                     None
                 }
                 HashEntry::Vacant(exchange) => Some(Error::Needless {
@@ -141,6 +650,125 @@ where
     }
 }
 
+/// Uses the reverse index to avoid scanning every level.
+fn remove_exchange_from_side<QuantityT, PriceT, ExchangeIdT, BuildHasherT>(
+    side: &mut Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+    exchange_id: &ExchangeIdT,
+) where
+    PriceT: Ord,
+    BuildHasherT: BuildHasher,
+    ExchangeIdT: Eq + Hash,
+{
+    let prices = side.by_exchange.remove(exchange_id).map(|it| it.prices);
+    for price in prices.into_iter().flatten() {
+        if let TreeEntry::Occupied(mut price_level) = side.levels.entry(price) {
+            price_level.get_mut().remove(exchange_id);
+            if price_level.get().is_empty() {
+                price_level.remove();
+            }
+        }
+    }
+}
+
+/// A single exchange's view of an [`ArbitrageFinder`], see [`ArbitrageFinder::exchange`].
+#[derive(Debug)]
+pub struct ExchangeBook<'a, QuantityT, PriceT, ExchangeIdT, BuildHasherT = RandomState> {
+    finder: &'a ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+    exchange_id: &'a ExchangeIdT,
+}
+
+impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT> Clone
+    for ExchangeBook<'_, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT> Copy
+    for ExchangeBook<'_, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+{
+}
+
+impl<'a, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+    ExchangeBook<'a, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    PriceT: Ord,
+    ExchangeIdT: Eq + Hash,
+    BuildHasherT: BuildHasher,
+{
+    /// The highest bid on this exchange.
+    pub fn best_bid(&self) -> Option<(&'a PriceT, &'a QuantityT)> {
+        self.levels(Side::Bid).next()
+    }
+    /// The lowest ask on this exchange.
+    pub fn best_ask(&self) -> Option<(&'a PriceT, &'a QuantityT)> {
+        self.levels(Side::Ask).next()
+    }
+    /// Levels on `side`, best first.
+    pub fn levels(&self, side: Side) -> impl Iterator<Item = (&'a PriceT, &'a QuantityT)> {
+        let half = self.half(side);
+        let exchange_id = self.exchange_id;
+        let prices = half
+            .by_exchange
+            .get(exchange_id)
+            .into_iter()
+            .flat_map(|it| &it.prices);
+        match side {
+            Side::Bid => Either::Left(prices.rev()),
+            Side::Ask => Either::Right(prices),
+        }
+        .map(move |price| (price, &half.levels[price][exchange_id]))
+    }
+    /// The number of price levels on `side`.
+    pub fn len(&self, side: Side) -> usize {
+        self.half(side)
+            .by_exchange
+            .get(self.exchange_id)
+            .map(|it| it.prices.len())
+            .unwrap_or_default()
+    }
+    /// Whether this exchange has no levels on either side.
+    pub fn is_empty(&self) -> bool {
+        self.len(Side::Bid) == 0 && self.len(Side::Ask) == 0
+    }
+    /// Halfway between [`Self::best_bid`] and [`Self::best_ask`].
+    pub fn mid(&self) -> Option<PriceT>
+    where
+        PriceT: Clone + NumOps + One,
+    {
+        Some(mid(self.best_bid()?.0, self.best_ask()?.0))
+    }
+    /// [`Self::best_ask`] less [`Self::best_bid`].
+    ///
+    /// Returns [`None`] if either side is empty, or if the spread isn't representable.
+    pub fn spread(&self) -> Option<PriceT>
+    where
+        PriceT: CheckedSub,
+    {
+        self.best_ask()?.0.checked_sub(self.best_bid()?.0)
+    }
+    /// Total quantity on `side`.
+    ///
+    /// This is kept up to date as levels change, so doesn't walk the book.
+    pub fn depth(&self, side: Side) -> QuantityT
+    where
+        QuantityT: Zero + Clone,
+    {
+        self.half(side)
+            .by_exchange
+            .get(self.exchange_id)
+            .map(|it| it.depth.clone())
+            .unwrap_or_else(QuantityT::zero)
+    }
+    fn half(&self, side: Side) -> &'a Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT> {
+        match side {
+            Side::Bid => &self.finder.bids,
+            Side::Ask => &self.finder.asks,
+        }
+    }
+}
+
 impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT> Default
     for ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
 where
@@ -150,6 +778,20 @@ where
         Self {
             bids: Default::default(),
             asks: Default::default(),
+            fees: Default::default(),
+        }
+    }
+}
+
+impl<QuantityT, PriceT, ExchangeIdT, BuildHasherT> Default
+    for Half<QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    BuildHasherT: Default,
+{
+    fn default() -> Self {
+        Self {
+            levels: Default::default(),
+            by_exchange: Default::default(),
         }
     }
 }
@@ -163,6 +805,9 @@ mod tests {
 
     type Finder<Q, P, E> = ArbitrageFinder<Q, P, E>;
 
+    #[allow(non_camel_case_types)]
+    type u16f16 = fixed::FixedU32<typenum::U16>;
+
     #[test]
     fn sell_highest_spread_first() {
         let mut arbitrage = Finder::default();
@@ -172,7 +817,7 @@ mod tests {
         assert_empty(arbitrage.buy("kraken", 40, 1).unwrap());
 
         assert_equal(
-            arbitrage.sell("binance", 20, 1).unwrap(),
+            levels(arbitrage.sell("binance", 20, 1).unwrap()),
             [(&"kraken", &40, &1), (&"kraken", &30, &1)],
         );
     }
@@ -186,11 +831,362 @@ mod tests {
         assert_empty(arbitrage.sell("kraken", 40, 1).unwrap());
 
         assert_equal(
-            arbitrage.buy("binance", 30, 1).unwrap(),
+            levels(arbitrage.buy("binance", 30, 1).unwrap()),
             [(&"kraken", &10, &1), (&"kraken", &20, &1)],
         );
     }
 
+    #[test]
+    fn sell_removes_ask() {
+        let mut arbitrage = Finder::default();
+        assert_empty(arbitrage.sell("kraken", 10, 1).unwrap());
+        assert_empty(arbitrage.sell("kraken", 10, 0).unwrap());
+        assert_empty(arbitrage.buy("binance", 30, 1).unwrap());
+        assert_eq!(
+            arbitrage.sell("kraken", 10, 0).err(),
+            Some(Error::Needless {
+                exchange_id: "kraken"
+            })
+        );
+    }
+
+    #[test]
+    fn fees_eat_spread() {
+        let mut arbitrage = Finder::default();
+        arbitrage.set_fees(
+            "kraken",
+            Fees {
+                maker: u16f16::ZERO,
+                taker: u16f16::lit("0.125"),
+            },
+        );
+        arbitrage.set_fees(
+            "binance",
+            Fees {
+                maker: u16f16::ZERO,
+                taker: u16f16::lit("0.0625"),
+            },
+        );
+        assert_empty(arbitrage.buy("kraken", u16f16::lit("100"), 1).unwrap());
+        assert_empty(arbitrage.buy("kraken", u16f16::lit("120"), 1).unwrap());
+
+        // 100 * 0.875 = 87.5 < 95.625 = 90 * 1.0625
+        // 120 * 0.875 = 105  > 95.625 = 90 * 1.0625
+        assert_equal(
+            arbitrage.sell("binance", u16f16::lit("90"), 1).unwrap(),
+            [Opportunity {
+                exchange_id: &"kraken",
+                price: &u16f16::lit("120"),
+                quantity: &1,
+                gross_spread: u16f16::lit("30"),
+                net_spread: u16f16::lit("9.375"),
+            }],
+        );
+    }
+
+    #[test]
+    fn free_exchanges_report_gross() {
+        let mut arbitrage = Finder::default();
+        assert_empty(arbitrage.sell("kraken", 10, 1).unwrap());
+        assert_equal(
+            arbitrage.buy("binance", 30, 1).unwrap(),
+            [Opportunity {
+                exchange_id: &"kraken",
+                price: &10,
+                quantity: &1,
+                gross_spread: 20,
+                net_spread: 20,
+            }],
+        );
+    }
+
+    #[test]
+    fn plan_walks_depth() {
+        let mut arbitrage = Finder::default();
+        assert_empty(arbitrage.sell("kraken", 10, 2).unwrap());
+        assert_empty(arbitrage.sell("coinbase", 12, 3).unwrap());
+        assert_empty(arbitrage.sell("kraken", 14, 5).unwrap());
+        assert_empty(arbitrage.sell("binance", 11, 100).unwrap()); // same exchange
+        assert_empty(arbitrage.sell("coinbase", 20, 100).unwrap()); // too expensive
+
+        assert_eq!(
+            arbitrage.plan_buy("binance", 16, 6),
+            Some(Plan {
+                quantity: 6,
+                buy_price: (10 * 2 + 12 * 3 + 14) / 6,
+                sell_price: 16,
+                profit: 6 * 2 + 4 * 3 + 2,
+                fills: vec![
+                    Fill {
+                        exchange_id: "kraken",
+                        price: 10,
+                        quantity: 2
+                    },
+                    Fill {
+                        exchange_id: "coinbase",
+                        price: 12,
+                        quantity: 3
+                    },
+                    Fill {
+                        exchange_id: "kraken",
+                        price: 14,
+                        quantity: 1
+                    },
+                ]
+            })
+        );
+        assert_eq!(arbitrage.plan_buy("binance", 10, 6), None);
+    }
+
+    #[test]
+    fn plan_takes_best_net_spread_first() {
+        let mut arbitrage = Finder::default();
+        arbitrage.set_fees(
+            "kraken",
+            Fees {
+                maker: u16f16::ZERO,
+                taker: u16f16::lit("0.5"),
+            },
+        );
+        let lit = u16f16::lit;
+        // costs 15 after fees
+        assert_empty(arbitrage.sell("kraken", lit("10"), 1).unwrap());
+        assert_empty(arbitrage.sell("coinbase", lit("12"), 1).unwrap());
+
+        let plan = arbitrage.plan_buy("binance", lit("20"), 1).unwrap();
+        assert_eq!(
+            plan.fills,
+            [Fill {
+                exchange_id: "coinbase",
+                price: lit("12"),
+                quantity: 1
+            }]
+        );
+        assert_eq!(plan.profit, lit("8"));
+    }
+
+    #[test]
+    fn plan_is_limited_by_depth() {
+        let mut arbitrage = Finder::default();
+        assert_empty(arbitrage.buy("kraken", 30, 1).unwrap());
+        assert_empty(arbitrage.buy("coinbase", 20, 1).unwrap());
+
+        assert_eq!(
+            arbitrage.plan_sell("binance", 10, 100),
+            Some(Plan {
+                quantity: 2,
+                buy_price: 10,
+                sell_price: 25,
+                profit: 30,
+                fills: vec![
+                    Fill {
+                        exchange_id: "kraken",
+                        price: 30,
+                        quantity: 1
+                    },
+                    Fill {
+                        exchange_id: "coinbase",
+                        price: 20,
+                        quantity: 1
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn replace_drops_stale_levels() {
+        let mut arbitrage = Finder::default();
+        assert_empty(arbitrage.sell("kraken", 10, 1).unwrap());
+        assert_empty(arbitrage.sell("kraken", 20, 1).unwrap());
+        assert_empty(arbitrage.sell("binance", 20, 1).unwrap());
+
+        arbitrage.replace("kraken", Side::Ask, [(25, 1), (30, 0)]);
+        assert_equal(
+            levels(arbitrage.buy("coinbase", 100, 1).unwrap()),
+            [(&"binance", &20, &1), (&"kraken", &25, &1)],
+        );
+        assert_eq!(
+            arbitrage.sell("kraken", 10, 0).err(),
+            Some(Error::Needless {
+                exchange_id: "kraken"
+            })
+        );
+    }
+
+    #[test]
+    fn remove_exchange_purges_both_sides() {
+        let mut arbitrage = Finder::default();
+        assert_empty(arbitrage.sell("kraken", 10, 1).unwrap());
+        assert_empty(arbitrage.sell("binance", 10, 1).unwrap());
+        assert_empty(arbitrage.buy("kraken", 5, 1).unwrap());
+
+        arbitrage.remove_exchange(&"kraken");
+        assert_equal(
+            levels(arbitrage.buy("coinbase", 100, 1).unwrap()),
+            [(&"binance", &10, &1)],
+        );
+        assert_empty(arbitrage.sell("coinbase", 1, 1).unwrap());
+    }
+
+    #[test]
+    fn exchange_book() {
+        let mut arbitrage = Finder::default();
+        assert_empty(arbitrage.buy("kraken", 10, 1).unwrap());
+        assert_empty(arbitrage.buy("kraken", 20, 2).unwrap());
+        assert_empty(arbitrage.sell("kraken", 30, 3).unwrap());
+        assert_empty(arbitrage.sell("kraken", 40, 4).unwrap());
+        assert_empty(arbitrage.buy("binance", 25, 1).unwrap());
+
+        let kraken = arbitrage.exchange(&"kraken");
+        assert_eq!(kraken.best_bid(), Some((&20, &2)));
+        assert_eq!(kraken.best_ask(), Some((&30, &3)));
+        assert_equal(kraken.levels(Side::Bid), [(&20, &2), (&10, &1)]);
+        assert_equal(kraken.levels(Side::Ask), [(&30, &3), (&40, &4)]);
+        assert_eq!(kraken.len(Side::Bid), 2);
+        assert_eq!(kraken.depth(Side::Ask), 7);
+
+        assert_empty(arbitrage.buy("kraken", 20, 0).unwrap());
+        assert_eq!(arbitrage.exchange(&"kraken").best_bid(), Some((&10, &1)));
+
+        // depth follows updates, removals and replacements
+        assert_empty(arbitrage.sell("kraken", 30, 1).unwrap());
+        assert_eq!(arbitrage.exchange(&"kraken").depth(Side::Ask), 5);
+        assert_empty(arbitrage.sell("kraken", 40, 0).unwrap());
+        assert_eq!(arbitrage.exchange(&"kraken").depth(Side::Ask), 1);
+        arbitrage.replace("kraken", Side::Bid, [(9, 5), (8, 6)]);
+        assert_eq!(arbitrage.exchange(&"kraken").depth(Side::Bid), 11);
+        assert_eq!(arbitrage.exchange(&"binance").depth(Side::Bid), 1);
+
+        arbitrage.remove_exchange(&"kraken");
+        assert!(arbitrage.exchange(&"kraken").is_empty());
+        assert_eq!(arbitrage.exchange(&"kraken").depth(Side::Bid), 0);
+        assert_eq!(arbitrage.exchange(&"binance").best_bid(), Some((&25, &1)));
+    }
+
+    #[test]
+    fn queries() {
+        let mut arbitrage = Finder::default();
+        assert_eq!(arbitrage.best_bid(), None);
+        assert_eq!(arbitrage.mid(), None);
+        assert_eq!(arbitrage.spread(), None);
+
+        assert_empty(arbitrage.buy("kraken", 10, 1).unwrap());
+        assert_empty(arbitrage.buy("binance", 10, 2).unwrap());
+        assert_empty(arbitrage.buy("kraken", 8, 1).unwrap());
+        assert_empty(arbitrage.sell("binance", 20, 3).unwrap());
+        assert_empty(arbitrage.sell("kraken", 30, 4).unwrap());
+
+        assert_eq!(
+            arbitrage.best_bid(),
+            Some((&10, &HashMap::from_iter([("kraken", 1), ("binance", 2)])))
+        );
+        assert_eq!(
+            arbitrage.best_ask(),
+            Some((&20, &HashMap::from_iter([("binance", 3)])))
+        );
+        assert_eq!(arbitrage.mid(), Some(15));
+        assert_eq!(arbitrage.spread(), Some(10));
+        assert_equal(
+            arbitrage.levels(Side::Bid).map(|(price, _)| price),
+            [&10, &8],
+        );
+
+        let kraken = arbitrage.exchange(&"kraken");
+        assert_eq!(kraken.mid(), Some(20));
+        assert_eq!(kraken.spread(), Some(20));
+    }
+
+    #[test]
+    fn crossed_spread() {
+        let mut arbitrage = Finder::<u32, u32, _>::default();
+        assert_empty(arbitrage.buy("kraken", 20, 1).unwrap());
+        assert_equal(
+            levels(arbitrage.sell("binance", 10, 1).unwrap()),
+            [(&"kraken", &20, &1)],
+        );
+        assert_eq!(arbitrage.spread(), None);
+
+        let mut arbitrage = Finder::<i32, i32, _>::default();
+        assert_empty(arbitrage.buy("kraken", 20, 1).unwrap());
+        assert_equal(
+            levels(arbitrage.sell("binance", 10, 1).unwrap()),
+            [(&"kraken", &20, &1)],
+        );
+        assert_eq!(arbitrage.spread(), Some(-10));
+    }
+
+    #[test]
+    fn crossings_are_ordered_by_profit() {
+        let mut arbitrage = Finder::default();
+        arbitrage.set_fees(
+            "coinbase",
+            Fees {
+                maker: u16f16::ZERO,
+                taker: u16f16::lit("0.5"),
+            },
+        );
+        let lit = u16f16::lit;
+        assert_empty(arbitrage.sell("kraken", lit("10"), 1).unwrap());
+        assert_empty(arbitrage.sell("binance", lit("12"), 2).unwrap());
+        // only crosses kraken's own ask, which isn't an arbitrage
+        assert_empty(arbitrage.buy("kraken", lit("11"), 1).unwrap());
+        // each of these was reported once, when it arrived
+        assert_equal(
+            levels(arbitrage.buy("binance", lit("20"), 5).unwrap()),
+            [(&"kraken", &lit("10"), &1)],
+        );
+        assert_equal(
+            levels(arbitrage.buy("coinbase", lit("30"), 5).unwrap()),
+            [(&"kraken", &lit("10"), &1), (&"binance", &lit("12"), &2)],
+        );
+
+        // the wider spread on a single unit ranks below the narrower one on two
+        assert_equal(
+            arbitrage.crossings().into_iter().map(
+                |Crossing {
+                     bid_exchange_id,
+                     ask_exchange_id,
+                     quantity,
+                     net_spread,
+                     profit,
+                     ..
+                 }| {
+                    (
+                        *bid_exchange_id,
+                        *ask_exchange_id,
+                        quantity,
+                        net_spread,
+                        profit,
+                    )
+                },
+            ),
+            [
+                ("binance", "kraken", 1, lit("10"), lit("10")),
+                ("coinbase", "binance", 2, lit("3"), lit("6")),
+                ("coinbase", "kraken", 1, lit("5"), lit("5")),
+            ],
+        );
+    }
+
+    fn levels<'a, Q, P, E>(
+        it: impl IntoIterator<Item = Opportunity<'a, Q, P, E>>,
+    ) -> impl Iterator<Item = (&'a E, &'a P, &'a Q)>
+    where
+        Q: 'a,
+        P: 'a,
+        E: 'a,
+    {
+        it.into_iter().map(
+            |Opportunity {
+                 exchange_id,
+                 price,
+                 quantity,
+                 ..
+             }| (exchange_id, price, quantity),
+        )
+    }
+
     fn assert_empty<T>(it: impl IntoIterator<Item = T>)
     where
         T: Debug + PartialEq,
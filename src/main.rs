@@ -1,10 +1,14 @@
-use std::{cmp, pin::pin};
+use std::rc::Rc;
 
 use clap::Parser;
-use futures::{stream, StreamExt as _};
+use futures::{future, stream, stream::LocalBoxStream, Stream, StreamExt as _};
 use openhedge_arbitrage::{
-    integrations::{aevo, dydx, ExchangeMessage},
-    ArbitrageFinder,
+    integrations::{
+        reconnecting, subscribe_with, Aevo, Backoff, Config, Dydx, Endpoint, Exchange,
+        ExchangeMessage, FeedError, Proxy,
+    },
+    markets::Markets,
+    Fees, Plan,
 };
 use tracing::{error, info, trace};
 
@@ -12,19 +16,44 @@ use tracing::{error, info, trace};
 type u32f32 = fixed::FixedU64<typenum::U32>;
 
 #[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
-enum Exchange {
+enum Venue {
     Aevo,
     Dydx,
 }
 
+/// Messages from a single venue and symbol, see [`tagged`].
+type Feed = LocalBoxStream<
+    'static,
+    (
+        Venue,
+        Rc<str>,
+        Option<tungstenite::Result<ExchangeMessage<u32f32, u32f32>>>,
+    ),
+>;
+
 #[derive(Parser)]
 struct Args {
     /// Omit `TRACE` logs
     #[arg(short, long)]
     quiet: bool,
-    /// Don't stop at the first error - log and continue.
+    /// Log parse and config errors, rather than exiting.
     #[arg(short, long)]
     r#continue: bool,
+    /// Connect to each venue's testnet.
+    #[arg(long)]
+    testnet: bool,
+    /// Proxy for Aevo connections, e.g `socks5://host:port`.
+    #[arg(long)]
+    aevo_proxy: Option<Proxy>,
+    /// Proxy for dYdX connections, e.g `http://host:port`.
+    #[arg(long)]
+    dydx_proxy: Option<Proxy>,
+    /// Taker fee on Aevo, as a fraction of notional.
+    #[arg(long, default_value_t = u32f32::ZERO)]
+    aevo_taker_fee: u32f32,
+    /// Taker fee on dYdX, as a fraction of notional.
+    #[arg(long, default_value_t = u32f32::ZERO)]
+    dydx_taker_fee: u32f32,
 }
 
 #[tokio::main]
@@ -34,7 +63,15 @@ async fn main() {
         layer::SubscriberExt as _,
         util::SubscriberInitExt as _,
     };
-    let Args { quiet, r#continue } = Args::parse();
+    let Args {
+        quiet,
+        r#continue,
+        testnet,
+        aevo_proxy,
+        dydx_proxy,
+        aevo_taker_fee,
+        dydx_taker_fee,
+    } = Args::parse();
     tracing_subscriber::fmt()
         .with_test_writer()
         .with_max_level(match quiet {
@@ -49,61 +86,217 @@ async fn main() {
         }))
         .init();
 
-    _main(r#continue).await
+    let config = Config {
+        endpoint: match testnet {
+            true => Endpoint::Testnet,
+            false => Endpoint::Mainnet,
+        },
+        ..Default::default()
+    };
+    let mut markets = Markets::default();
+    let feeds = vec![
+        feed(
+            &mut markets,
+            Config {
+                proxy: aevo_proxy,
+                ..config.clone()
+            },
+            Venue::Aevo,
+            Aevo,
+            "BTC",
+            "USD",
+        ),
+        feed(
+            &mut markets,
+            Config {
+                proxy: dydx_proxy,
+                ..config
+            },
+            Venue::Dydx,
+            Dydx,
+            "BTC",
+            "USD",
+        ),
+    ];
+    for (_, finder) in markets.iter_mut() {
+        for (exchange, taker) in [(Venue::Aevo, aevo_taker_fee), (Venue::Dydx, dydx_taker_fee)] {
+            finder.set_fees(
+                exchange,
+                Fees {
+                    maker: u32f32::ZERO,
+                    taker,
+                },
+            );
+        }
+    }
+
+    _main(markets, feeds, r#continue).await
+}
+
+/// Subscribe to `base` in `quote` on `exchange`, and map it in `markets`.
+fn feed<ExchangeT>(
+    markets: &mut Markets<&'static str, u32f32, u32f32, Venue>,
+    config: Config,
+    venue: Venue,
+    exchange: ExchangeT,
+    base: &'static str,
+    quote: &str,
+) -> Feed
+where
+    ExchangeT: Exchange<u32f32, u32f32> + Clone + 'static,
+{
+    let venue_symbol = exchange.symbol(base, quote);
+    markets.map(venue, venue_symbol.clone(), base);
+    tagged(
+        venue,
+        venue_symbol.clone().into(),
+        reconnecting(
+            move || subscribe_with(exchange.clone(), venue_symbol.clone(), config.clone()),
+            Backoff::default(),
+        ),
+    )
+    .boxed_local()
 }
 
-async fn _main(no_fail_fast: bool) {
-    let mut finder = ArbitrageFinder::<_, _, _>::default();
-    let mut messages = pin!(stream::select(
-        aevo::<u32f32, u32f32>("BTC-PERP").map(|it| (Exchange::Aevo, it)),
-        dydx("BTC-USD").map(|it| (Exchange::Dydx, it))
-    ));
+async fn _main(
+    mut markets: Markets<&'static str, u32f32, u32f32, Venue>,
+    feeds: Vec<Feed>,
+    no_fail_fast: bool,
+) {
+    let mut messages = stream::select_all(feeds);
     let mut balance = u32f32::ZERO;
     loop {
-        let Some((src, msg)) = messages.next().await else {
-            error!("both streams terminated, exiting application");
+        let Some((src, venue_symbol, msg)) = messages.next().await else {
+            error!("every stream terminated, exiting application");
             std::process::exit(1);
         };
 
-        let msg = match msg {
-            Ok(msg) => {
-                trace!(?src, ?msg, "received message");
-                msg
-            }
-            Err(error) => {
-                // Aevo seems to randomly set huge prices to zero, giving us a parse error.
-                // e.g: "bids":[["115792089237316200000000000000000000000000000000000000000000000000000000","0"]]
-                error!(?src, %error);
-                match no_fail_fast {
-                    true => continue,
-                    false => std::process::exit(1),
-                }
-            }
+        let (symbol, msg) = match route(&mut markets, src, &venue_symbol, msg) {
+            Ok(Some(it)) => it,
+            Ok(None) => continue,
+            Err(error) => match is_fatal(&error) && !no_fail_fast {
+                true => std::process::exit(1),
+                false => continue,
+            },
         };
+        let finder = markets.finder(&symbol).expect("message was just routed");
 
         // NOTE: we don't actually do any trading here, so we might farm a given arbitrage opportunity twice.
         //       but this is just a demo...
         match msg {
             ExchangeMessage::Buy { price, quantity } => {
-                if let Ok(Some((sell_exchange, sell_price, sell_quantity))) =
-                    finder.buy(src, price, quantity).map(|mut it| it.next())
+                if let Some(Plan {
+                    quantity,
+                    buy_price,
+                    profit,
+                    fills,
+                    ..
+                }) = finder.plan_buy(src, price, quantity)
                 {
-                    let spread = price - sell_price;
-                    let quantity = cmp::min(quantity, *sell_quantity);
-                    balance += spread * quantity;
-                    info!(new_balance = %balance, %spread, %quantity, buy = ?src, sell = ?sell_exchange, "simulated arbitrage");
-                };
+                    balance += profit;
+                    info!(new_balance = %balance, %symbol, %profit, %quantity, %buy_price, sell_price = %price, sell = ?src, buy = ?fills, "simulated arbitrage");
+                }
             }
             ExchangeMessage::Sell { price, quantity } => {
-                if let Ok(Some((buy_exchange, buy_price, buy_quantity))) =
-                    finder.sell(src, price, quantity).map(|mut it| it.next())
+                if let Some(Plan {
+                    quantity,
+                    sell_price,
+                    profit,
+                    fills,
+                    ..
+                }) = finder.plan_sell(src, price, quantity)
                 {
-                    let spread = buy_price - price;
-                    let quantity = cmp::min(quantity, *buy_quantity);
-                    balance += spread * quantity;
-                    info!(new_balance = %balance, %spread, %quantity, sell = ?src, buy = ?buy_exchange, "simulated arbitrage");
-                };
+                    balance += profit;
+                    info!(new_balance = %balance, %symbol, %profit, %quantity, buy_price = %price, %sell_price, buy = ?src, sell = ?fills, "simulated arbitrage");
+                }
             }
+            ExchangeMessage::Resynced => info!(?src, %symbol, "resynced"),
+        }
+    }
+}
+
+/// Apply `msg` from `src` to its market, returning its canonical symbol.
+///
+/// If the feed failed or ended, `src`'s levels in that market are removed,
+/// since they'd otherwise go stale while [`reconnecting`] backs off and resubscribes.
+#[allow(clippy::result_large_err)]
+fn route(
+    markets: &mut Markets<&'static str, u32f32, u32f32, Venue>,
+    src: Venue,
+    venue_symbol: &str,
+    msg: Option<tungstenite::Result<ExchangeMessage<u32f32, u32f32>>>,
+) -> tungstenite::Result<Option<(&'static str, ExchangeMessage<u32f32, u32f32>)>> {
+    let msg = match msg {
+        Some(Ok(msg)) => {
+            trace!(?src, ?msg, "received message");
+            msg
         }
+        Some(Err(error)) => {
+            // Aevo seems to randomly set huge prices to zero, giving us a parse error.
+            // e.g: "bids":[["115792089237316200000000000000000000000000000000000000000000000000000000","0"]]
+            error!(?src, %error);
+            markets.remove_route(&src, venue_symbol);
+            return Err(error);
+        }
+        None => {
+            error!(?src, %venue_symbol, "stream terminated, removing it from the book");
+            markets.remove_route(&src, venue_symbol);
+            return Ok(None);
+        }
+    };
+    match markets.route(src, venue_symbol, msg) {
+        Some((symbol, Ok(_))) => Ok(Some((*symbol, msg))),
+        Some((_, Err(_))) => Ok(None),
+        None => {
+            error!(?src, %venue_symbol, "no market for symbol");
+            Ok(None)
+        }
+    }
+}
+
+/// Whether `error` is a parse or configuration error, which reconnecting won't fix,
+/// rather than e.g a dropped connection.
+fn is_fatal(error: &tungstenite::Error) -> bool {
+    use std::io::ErrorKind::{InvalidData, InvalidInput, Unsupported};
+    match error {
+        tungstenite::Error::Url(_) => true,
+        tungstenite::Error::Io(_) if FeedError::of(error).is_some() => false,
+        tungstenite::Error::Io(it) => matches!(it.kind(), InvalidData | InvalidInput | Unsupported),
+        _ => false,
+    }
+}
+
+/// Tag each item with its source, yielding [`None`] when the stream terminates.
+fn tagged<T>(
+    src: Venue,
+    venue_symbol: Rc<str>,
+    s: impl Stream<Item = T>,
+) -> impl Stream<Item = (Venue, Rc<str>, Option<T>)> {
+    let end = (src, venue_symbol.clone(), None);
+    s.map(move |it| (src, venue_symbol.clone(), Some(it)))
+        .chain(stream::once(future::ready(end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_remove_levels() {
+        let mut markets = Markets::default();
+        markets.map(Venue::Aevo, "BTC-PERP", "BTC");
+        let buy = ExchangeMessage::Buy {
+            price: u32f32::ONE,
+            quantity: u32f32::ONE,
+        };
+        assert_eq!(
+            route(&mut markets, Venue::Aevo, "BTC-PERP", Some(Ok(buy))).unwrap(),
+            Some(("BTC", buy))
+        );
+        assert!(markets.finder(&"BTC").unwrap().best_bid().is_some());
+
+        let error = tungstenite::Error::ConnectionClosed;
+        assert!(route(&mut markets, Venue::Aevo, "BTC-PERP", Some(Err(error))).is_err());
+        assert!(markets.finder(&"BTC").unwrap().best_bid().is_none());
     }
 }
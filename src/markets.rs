@@ -0,0 +1,269 @@
+//! Arbitrage across several instruments at once.
+//!
+//! Exchanges name the same market differently (e.g `BTC-PERP` vs `BTC-USD`),
+//! so each venue's symbol is [mapped](Markets::map) to a canonical one.
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hash, RandomState},
+    iter,
+    ops::Sub,
+};
+
+use itertools::Either;
+use num_traits::{NumOps, Zero};
+
+use crate::{integrations::ExchangeMessage, ArbitrageFinder, Error, Opportunity};
+
+/// Routes [`ExchangeMessage`]s to a per-instrument [`ArbitrageFinder`], keyed by a
+/// canonical symbol.
+#[derive(Debug, Clone)]
+pub struct Markets<SymbolT, QuantityT, PriceT, ExchangeIdT, BuildHasherT = RandomState> {
+    /// Exchange, then venue symbol, to canonical symbol.
+    symbols: HashMap<ExchangeIdT, HashMap<String, SymbolT, BuildHasherT>, BuildHasherT>,
+    finders: HashMap<
+        SymbolT,
+        ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+        BuildHasherT,
+    >,
+}
+
+impl<SymbolT, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+    Markets<SymbolT, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    SymbolT: Eq + Hash + Clone,
+    PriceT: Ord + Clone + NumOps,
+    QuantityT: Zero + Clone + Sub<Output = QuantityT>,
+    ExchangeIdT: Eq + Hash + Clone,
+    BuildHasherT: BuildHasher + Default,
+{
+    /// Route messages about `venue_symbol` on `exchange_id` to the finder for `symbol`,
+    /// returning the previous mapping.
+    pub fn map(
+        &mut self,
+        exchange_id: ExchangeIdT,
+        venue_symbol: impl Into<String>,
+        symbol: SymbolT,
+    ) -> Option<SymbolT> {
+        self.finders.entry(symbol.clone()).or_default();
+        self.symbols
+            .entry(exchange_id)
+            .or_default()
+            .insert(venue_symbol.into(), symbol)
+    }
+    /// The canonical symbol for `venue_symbol` on `exchange_id`, if it has been [mapped](Self::map).
+    pub fn symbol(&self, exchange_id: &ExchangeIdT, venue_symbol: &str) -> Option<&SymbolT> {
+        self.symbols.get(exchange_id)?.get(venue_symbol)
+    }
+    /// Apply `message` to the finder for `venue_symbol`, returning its canonical symbol
+    /// and the result of [`ArbitrageFinder::buy`] or [`ArbitrageFinder::sell`].
+    ///
+    /// [`ExchangeMessage::Resynced`] removes everything `exchange_id` has in that market,
+    /// see [`Self::remove_route`].
+    ///
+    /// Returns [`None`] if `venue_symbol` hasn't been [mapped](Self::map) for `exchange_id`.
+    #[allow(clippy::type_complexity)]
+    pub fn route(
+        &mut self,
+        exchange_id: ExchangeIdT,
+        venue_symbol: &str,
+        message: ExchangeMessage<PriceT, QuantityT>,
+    ) -> Option<(
+        &SymbolT,
+        Result<
+            impl Iterator<Item = Opportunity<'_, QuantityT, PriceT, ExchangeIdT>>,
+            Error<ExchangeIdT>,
+        >,
+    )> {
+        let symbol = self.symbols.get(&exchange_id)?.get(venue_symbol)?;
+        let finder = self
+            .finders
+            .get_mut(symbol)
+            .expect("every mapped symbol has a finder");
+        let result = match message {
+            ExchangeMessage::Buy { price, quantity } => finder
+                .buy(exchange_id, price, quantity)
+                .map(|it| Either::Left(Either::Left(it))),
+            ExchangeMessage::Sell { price, quantity } => finder
+                .sell(exchange_id, price, quantity)
+                .map(|it| Either::Left(Either::Right(it))),
+            ExchangeMessage::Resynced => {
+                finder.remove_exchange(&exchange_id);
+                Ok(Either::Right(iter::empty()))
+            }
+        };
+        Some((symbol, result))
+    }
+    /// Remove everything `exchange_id` has in the market `venue_symbol` is mapped to,
+    /// e.g when that feed disconnects, leaving its other markets alone.
+    ///
+    /// Returns the canonical symbol, or [`None`] if `venue_symbol` hasn't been [mapped](Self::map).
+    pub fn remove_route(
+        &mut self,
+        exchange_id: &ExchangeIdT,
+        venue_symbol: &str,
+    ) -> Option<&SymbolT> {
+        let symbol = self.symbols.get(exchange_id)?.get(venue_symbol)?;
+        self.finders
+            .get_mut(symbol)
+            .expect("every mapped symbol has a finder")
+            .remove_exchange(exchange_id);
+        Some(symbol)
+    }
+    /// Remove everything `exchange_id` has in every market,
+    /// see [`ArbitrageFinder::remove_exchange`].
+    pub fn remove_exchange(&mut self, exchange_id: &ExchangeIdT) {
+        for finder in self.finders.values_mut() {
+            finder.remove_exchange(exchange_id)
+        }
+    }
+}
+
+impl<SymbolT, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+    Markets<SymbolT, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    SymbolT: Eq + Hash,
+    BuildHasherT: BuildHasher,
+{
+    pub fn finder(
+        &self,
+        symbol: &SymbolT,
+    ) -> Option<&ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>> {
+        self.finders.get(symbol)
+    }
+    pub fn finder_mut(
+        &mut self,
+        symbol: &SymbolT,
+    ) -> Option<&mut ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>> {
+        self.finders.get_mut(symbol)
+    }
+    /// Every market, in arbitrary order.
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            &SymbolT,
+            &ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+        ),
+    > {
+        self.finders.iter()
+    }
+    /// Every market, in arbitrary order.
+    pub fn iter_mut(
+        &mut self,
+    ) -> impl Iterator<
+        Item = (
+            &SymbolT,
+            &mut ArbitrageFinder<QuantityT, PriceT, ExchangeIdT, BuildHasherT>,
+        ),
+    > {
+        self.finders.iter_mut()
+    }
+}
+
+impl<SymbolT, QuantityT, PriceT, ExchangeIdT, BuildHasherT> Default
+    for Markets<SymbolT, QuantityT, PriceT, ExchangeIdT, BuildHasherT>
+where
+    BuildHasherT: Default,
+{
+    fn default() -> Self {
+        Self {
+            symbols: Default::default(),
+            finders: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::assert_equal;
+
+    use super::*;
+
+    #[test]
+    fn route_by_canonical_symbol() {
+        let mut markets = Markets::<_, _, _, _>::default();
+        markets.map("aevo", "BTC-PERP", "BTC");
+        markets.map("dydx", "BTC-USD", "BTC");
+        markets.map("dydx", "ETH-USD", "ETH");
+
+        let (symbol, opportunities) = markets
+            .route(
+                "aevo",
+                "BTC-PERP",
+                ExchangeMessage::Sell {
+                    price: 10,
+                    quantity: 1,
+                },
+            )
+            .unwrap();
+        assert_eq!(symbol, &"BTC");
+        assert_eq!(opportunities.unwrap().count(), 0);
+
+        let (_, opportunities) = markets
+            .route(
+                "dydx",
+                "ETH-USD",
+                ExchangeMessage::Buy {
+                    price: 20,
+                    quantity: 1,
+                },
+            )
+            .unwrap();
+        assert_eq!(opportunities.unwrap().count(), 0);
+
+        let (symbol, opportunities) = markets
+            .route(
+                "dydx",
+                "BTC-USD",
+                ExchangeMessage::Buy {
+                    price: 20,
+                    quantity: 1,
+                },
+            )
+            .unwrap();
+        assert_eq!(symbol, &"BTC");
+        assert_equal(
+            opportunities
+                .unwrap()
+                .map(|it| (*it.exchange_id, *it.price)),
+            [("aevo", 10)],
+        );
+
+        assert!(markets
+            .route(
+                "aevo",
+                "BTC-USD",
+                ExchangeMessage::Buy {
+                    price: 20,
+                    quantity: 1
+                }
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn remove_route_leaves_other_markets() {
+        let mut markets = Markets::<_, u32, u32, _>::default();
+        markets.map("dydx", "BTC-USD", "BTC");
+        markets.map("dydx", "ETH-USD", "ETH");
+        for venue_symbol in ["BTC-USD", "ETH-USD"] {
+            let (_, result) = markets
+                .route(
+                    "dydx",
+                    venue_symbol,
+                    ExchangeMessage::Buy {
+                        price: 10,
+                        quantity: 1,
+                    },
+                )
+                .unwrap();
+            assert!(result.is_ok());
+        }
+
+        assert_eq!(markets.remove_route(&"dydx", "BTC-USD"), Some(&"BTC"));
+        assert!(markets.finder(&"BTC").unwrap().best_bid().is_none());
+        assert!(markets.finder(&"ETH").unwrap().best_bid().is_some());
+        assert_eq!(markets.remove_route(&"dydx", "SOL-USD"), None);
+    }
+}
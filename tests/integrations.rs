@@ -1,24 +1,128 @@
-//! Both integrations right now start off with an orderbook snapshot.
+//! All integrations right now start off with an orderbook snapshot.
 //! We know we've successfully processed that once we start seeing empty price
 //! levels.
+//!
+//! Each test's endpoint can be overridden with e.g `DYDX_ENDPOINT=testnet`,
+//! or `DYDX_ENDPOINT=ws://localhost:8080` for a local stand-in server,
+//! and its connection proxied with e.g `DYDX_PROXY=socks5://localhost:1080`.
+//! Binance's snapshots can be pointed at a stand-in too, with e.g
+//! `BINANCE_SPOT_REST_ENDPOINT=http://localhost:8080/api/v3/depth`.
 
 use std::{fmt::Debug, pin::pin};
 
 use futures::{Stream, StreamExt as _};
 use num_traits::Zero;
-use openhedge_arbitrage::integrations::{self, ExchangeMessage};
+use openhedge_arbitrage::integrations::{self, BinanceMarket, Config, ExchangeMessage};
 
 #[allow(non_camel_case_types)]
 type u32f32 = fixed::FixedU64<typenum::U32>;
 
 #[tokio::test]
 async fn dydx() {
-    test(integrations::dydx::<u32f32, u32f32>("BTC-USD")).await;
+    test(integrations::dydx_with::<u32f32, u32f32>(
+        "BTC-USD",
+        config("DYDX"),
+    ))
+    .await;
 }
 
 #[tokio::test]
 async fn aevo() {
-    test(integrations::aevo::<u32f32, u32f32>("BTC-PERP")).await;
+    test(integrations::aevo_with::<u32f32, u32f32>(
+        "BTC-PERP",
+        config("AEVO"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn binance_spot() {
+    test(integrations::binance_with::<u32f32, u32f32>(
+        "BTCUSDT",
+        BinanceMarket::Spot,
+        config("BINANCE_SPOT"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn binance_futures() {
+    test(integrations::binance_with::<u32f32, u32f32>(
+        "BTCUSDT",
+        BinanceMarket::UsdMFutures,
+        config("BINANCE_FUTURES"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn kraken() {
+    test(integrations::kraken_with::<u32f32, u32f32>(
+        "BTC/USD",
+        config("KRAKEN"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn coinbase() {
+    test(integrations::coinbase_with::<u32f32, u32f32>(
+        "BTC-USD",
+        config("COINBASE"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn okx() {
+    test(integrations::okx_with::<u32f32, u32f32>(
+        "BTC-USDT",
+        config("OKX"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn bybit() {
+    test(integrations::bybit_with::<u32f32, u32f32>(
+        "BTCUSDT",
+        integrations::BybitDepth::Fifty,
+        config("BYBIT"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn hyperliquid() {
+    test(integrations::hyperliquid_with::<u32f32, u32f32>(
+        "BTC",
+        config("HYPERLIQUID"),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn deribit() {
+    test(integrations::deribit_with::<u32f32, u32f32>(
+        "BTC-PERPETUAL",
+        config("DERIBIT"),
+    ))
+    .await;
+}
+
+/// Read `{venue}_ENDPOINT`, `{venue}_REST_ENDPOINT` and `{venue}_PROXY`,
+/// see the module documentation.
+fn config(venue: &str) -> Config {
+    Config {
+        endpoint: std::env::var(format!("{venue}_ENDPOINT"))
+            .map(|it| it.parse().unwrap())
+            .unwrap_or_default(),
+        proxy: std::env::var(format!("{venue}_PROXY"))
+            .ok()
+            .map(|it| it.parse().unwrap()),
+        rest_endpoint: std::env::var(format!("{venue}_REST_ENDPOINT")).ok(),
+        ..Default::default()
+    }
 }
 
 async fn test<PriceT, QuantityT, E>(